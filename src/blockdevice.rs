@@ -0,0 +1,70 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+pub const BLOCK_SIZE: usize = 512;
+pub const NUM_BLOCKS: usize = 2048;
+pub const DISK_IMAGE: &str = "minos.img";
+
+pub(crate) type Block = [u8; BLOCK_SIZE];
+
+/// A disk made of fixed size numbered blocks, stored in a single host file
+pub struct BlockDevice {
+    file: File,
+    num_blocks: usize,
+}
+
+impl BlockDevice {
+    /// Opens a disk image, creating a zeroed one of `num_blocks` blocks if it doesn't exist
+    pub(crate) fn open(path: &str, num_blocks: usize) -> io::Result<BlockDevice> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let len = file.metadata()?.len() as usize;
+        let num_blocks = if len == 0 {
+            file.set_len((num_blocks * BLOCK_SIZE) as u64)?;
+            num_blocks
+        } else {
+            len / BLOCK_SIZE
+        };
+
+        Ok(BlockDevice{file, num_blocks})
+    }
+
+    pub(crate) fn num_blocks(&self) -> usize {
+        self.num_blocks
+    }
+
+    fn seek(&mut self, block: usize) -> io::Result<()> {
+        if block >= self.num_blocks {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("block {block} out of range [0, {})", self.num_blocks)
+            ))
+        }
+        self.file.seek(SeekFrom::Start((block * BLOCK_SIZE) as u64))?;
+        Ok(())
+    }
+
+    pub(crate) fn read_block(&mut self, block: usize, buf: &mut Block) -> io::Result<()> {
+        self.seek(block)?;
+        self.file.read_exact(buf)
+    }
+
+    pub(crate) fn write_block(&mut self, block: usize, buf: &Block) -> io::Result<()> {
+        self.seek(block)?;
+        self.file.write_all(buf)
+    }
+
+    /// A blank disk has never been formatted, its first block is all zeroes
+    pub(crate) fn is_blank(&mut self) -> io::Result<bool> {
+        let mut buf = [0; BLOCK_SIZE];
+        self.read_block(0, &mut buf)?;
+        Ok(buf.iter().all(|b| *b == 0))
+    }
+}
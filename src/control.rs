@@ -0,0 +1,336 @@
+use std::collections::{HashMap, VecDeque};
+use crate::interpreter::{err_msg, expand_words, run_commands, Status, SUCCESS};
+use crate::job::Job;
+use crate::kernel::Kernel;
+use crate::parser::{parse, Command};
+
+pub(crate) const MAX_CALL_DEPTH: usize = 64;
+
+/// What a control line of a script does, with the lines it jumps to worked out at load time.
+/// Lines are program counters, i.e. offsets in the code segment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Control {
+    If { else_pc: Option<usize>, fi_pc: usize },
+    Else { fi_pc: usize },
+    While { done_pc: usize },
+    For { done_pc: usize },
+    Done { head_pc: usize },
+    Function { end_pc: usize },
+    FunctionEnd,
+    Return,
+    Nop, // `then`, `do` and `fi` on a line of their own
+}
+
+/// Where a script's functions start and how its blocks nest
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ControlTable {
+    pub(crate) lines: HashMap<usize, Control>,
+    pub(crate) functions: HashMap<String, usize>, // Name to the line of its header
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct CallFrame {
+    pub(crate) return_pc: usize,
+    pub(crate) saved_args: Vec<(String, Option<String>)>, // Positional variables shadowed by the call
+}
+
+/// What is left of a `for` loop, kept by the job until the loop ends
+#[derive(Clone, Debug)]
+pub(crate) struct ForLoop {
+    pub(crate) var: String,
+    pub(crate) items: VecDeque<String>,
+}
+
+/// The keyword a line starts with, if its first word is a plain unquoted word
+pub(crate) fn keyword(commands: &[Command]) -> Option<&str> {
+    commands.first()?.words.first()?.as_plain()
+}
+
+/// Matches the blocks of a script so that jumps don't need to scan for their target at run time.
+///
+/// ```text
+/// if COND [; then]        while COND [; do]       for VAR in WORDS [; do]     function NAME {
+/// then                    do                      do                              ...
+///     ...                     ...                     ...                         return [STATUS]
+/// else                    done                    done                        }
+///     ...
+/// fi
+/// ```
+pub(crate) fn analyze(lines: &[String]) -> Result<ControlTable, String> {
+    enum Open {
+        If { pc: usize, else_pc: Option<usize> },
+        Loop { pc: usize, is_for: bool },
+        Function { pc: usize, name: String },
+    }
+
+    let mut table = ControlTable::default();
+    let mut open: Vec<Open> = vec![];
+    let err = |pc: usize, msg: &str| Err(format!("syntax error on line {}: {}", pc + 1, msg));
+
+    for (pc, line) in lines.iter().enumerate() {
+        // Lines that don't parse fail when they run, like any other command
+        let Ok(commands) = parse(line) else {
+            continue
+        };
+        let Some(word) = keyword(&commands) else {
+            continue
+        };
+        match word {
+            "if" => open.push(Open::If{pc, else_pc: None}),
+            "else" => match open.last_mut() {
+                Some(Open::If{else_pc: else_pc @ None, ..}) => *else_pc = Some(pc),
+                _ => return err(pc, "`else` without `if`"),
+            },
+            "fi" => match open.pop() {
+                Some(Open::If{pc: if_pc, else_pc}) => {
+                    table.lines.insert(if_pc, Control::If{else_pc, fi_pc: pc});
+                    if let Some(else_pc) = else_pc {
+                        table.lines.insert(else_pc, Control::Else{fi_pc: pc});
+                    }
+                    table.lines.insert(pc, Control::Nop);
+                }
+                _ => return err(pc, "`fi` without `if`"),
+            },
+            "while" | "for" => open.push(Open::Loop{pc, is_for: word == "for"}),
+            "done" => match open.pop() {
+                Some(Open::Loop{pc: head_pc, is_for}) => {
+                    let head = if is_for {
+                        Control::For{done_pc: pc}
+                    } else {
+                        Control::While{done_pc: pc}
+                    };
+                    table.lines.insert(head_pc, head);
+                    table.lines.insert(pc, Control::Done{head_pc});
+                }
+                _ => return err(pc, "`done` without `while` or `for`"),
+            },
+            "function" => {
+                let name = commands[0].words.get(1).and_then(|w| w.as_plain());
+                match name {
+                    Some(name) => open.push(Open::Function{pc, name: String::from(name)}),
+                    None => return err(pc, "usage: function NAME {"),
+                }
+            }
+            "}" => match open.pop() {
+                Some(Open::Function{pc: header_pc, name}) => {
+                    table.lines.insert(header_pc, Control::Function{end_pc: pc});
+                    table.lines.insert(pc, Control::FunctionEnd);
+                    table.functions.insert(name, header_pc);
+                }
+                _ => return err(pc, "`}` without `function`"),
+            },
+            "return" => {
+                if !open.iter().any(|o| matches!(o, Open::Function{..})) {
+                    return err(pc, "`return` outside of a function")
+                }
+                table.lines.insert(pc, Control::Return);
+            }
+            "then" | "do" => {
+                table.lines.insert(pc, Control::Nop);
+            }
+            _ => {}
+        }
+    }
+
+    match open.last() {
+        Some(Open::If{pc, ..}) => err(*pc, "`if` is never closed with `fi`"),
+        Some(Open::Loop{pc, ..}) => err(*pc, "loop is never closed with `done`"),
+        Some(Open::Function{pc, ..}) => err(*pc, "function is never closed with `}`"),
+        None => Ok(table),
+    }
+}
+
+/// Splits the header of an `if`, `while` or `for` into the words after the keyword,
+/// dropping a trailing `then` or `do` that shares its line
+pub(crate) fn header(mut commands: Vec<Command>) -> Vec<Command> {
+    if commands.len() > 1 && matches!(keyword(&commands[commands.len() - 1..]), Some("then" | "do")) {
+        commands.pop();
+    }
+    if let Some(first) = commands.first_mut() {
+        first.words.remove(0);
+    }
+    commands
+}
+
+/// Runs the line at `job.pc` and returns the pc of the line to run next.
+/// Jumps only change the pc, so a loop faults its pages in and is preempted like any other code.
+pub(crate) fn step(kernel: &mut Kernel, job: &mut Job, line: &str) -> usize {
+    let next = job.pc + 1;
+    let commands = match parse(line) {
+        Ok(c) => c,
+        Err(e) => return fail(kernel, e.as_str(), next),
+    };
+
+    let control = job.program.borrow().control.lines.get(&job.pc).copied();
+    let Some(control) = control else {
+        let function = keyword(&commands)
+            .and_then(|name| job.program.borrow().control.functions.get(name).copied());
+        return match function {
+            Some(header_pc) => call(kernel, job, header_pc, &commands),
+            None => {
+                run_commands(&commands, kernel);
+                next
+            }
+        }
+    };
+
+    match control {
+        Control::If{else_pc, fi_pc} => {
+            if condition(kernel, commands, "if") == SUCCESS {
+                next
+            } else {
+                else_pc.unwrap_or(fi_pc) + 1
+            }
+        }
+        Control::Else{fi_pc} => fi_pc + 1,
+        Control::While{done_pc} => {
+            if condition(kernel, commands, "while") == SUCCESS {
+                next
+            } else {
+                done_pc + 1
+            }
+        }
+        Control::For{done_pc} => {
+            // Entering the loop from above always starts it over, even if a `return` left it unfinished
+            let mut header = header(commands);
+            let words = match header.as_mut_slice() {
+                [command] => std::mem::take(&mut command.words),
+                _ => vec![],
+            };
+            let var = words.first().and_then(|w| w.as_plain()).map(String::from);
+            let is_in = words.get(1).and_then(|w| w.as_plain()) == Some("in");
+            let Some(var) = var.filter(|_| is_in) else {
+                return fail(kernel, "usage: for VAR in WORDS", done_pc + 1)
+            };
+            let items = expand_words(&words[2..], kernel).into();
+            job.loops.insert(job.pc, ForLoop{var, items});
+            next_item(kernel, job, job.pc, done_pc)
+        }
+        Control::Done{head_pc} => {
+            let is_for = matches!(job.program.borrow().control.lines.get(&head_pc), Some(Control::For{..}));
+            if is_for {
+                next_item(kernel, job, head_pc, job.pc)
+            } else {
+                head_pc
+            }
+        }
+        Control::Function{end_pc} => end_pc + 1,
+        Control::FunctionEnd => ret(kernel, job, next),
+        Control::Return => {
+            let args = expand_words(&commands[0].words[1..], kernel);
+            match args.as_slice() {
+                [] => {}
+                [status] => match status.parse::<Status>() {
+                    Ok(status) => kernel.set_status(status),
+                    Err(_) => return fail(kernel, format!("invalid return status: {status}").as_str(), next),
+                },
+                _ => return fail(kernel, "usage: return [STATUS]", next),
+            }
+            ret(kernel, job, next)
+        }
+        Control::Nop => next,
+    }
+}
+
+fn fail(kernel: &mut Kernel, msg: &str, next: usize) -> usize {
+    kernel.set_status(err_msg(&kernel.out, msg));
+    next
+}
+
+/// Runs the condition of an `if` or `while`, its status decides where the job goes
+fn condition(kernel: &mut Kernel, commands: Vec<Command>, keyword: &str) -> Status {
+    let commands = header(commands);
+    if commands.first().is_none_or(|c| c.words.is_empty()) {
+        let status = err_msg(&kernel.out, format!("usage: {keyword} COMMAND").as_str());
+        kernel.set_status(status);
+        return status
+    }
+    run_commands(&commands, kernel)
+}
+
+/// Sets the variable of a `for` loop to its next item, or leaves the loop once there are none left
+fn next_item(kernel: &mut Kernel, job: &mut Job, head_pc: usize, done_pc: usize) -> usize {
+    let Some(item) = job.loops.get_mut(&head_pc).and_then(|l| l.items.pop_front()) else {
+        job.loops.remove(&head_pc);
+        return done_pc + 1
+    };
+    let var = job.loops[&head_pc].var.clone();
+    if let Err(e) = kernel.get_varmem().borrow_mut().set(var, item) {
+        job.loops.remove(&head_pc);
+        return fail(kernel, e.to_string().as_str(), done_pc + 1)
+    }
+    head_pc + 1
+}
+
+/// Jumps into a function. Its arguments are `$1`, `$2`, ... and `$#`, the caller's are restored on return.
+fn call(kernel: &mut Kernel, job: &mut Job, header_pc: usize, commands: &[Command]) -> usize {
+    let next = job.pc + 1;
+    if commands.len() > 1 {
+        return fail(kernel, "a function call must be the only command on its line", next)
+    }
+    if job.frames.len() >= MAX_CALL_DEPTH {
+        return fail(kernel, format!("maximum call depth of {MAX_CALL_DEPTH} exceeded").as_str(), next)
+    }
+
+    let args = expand_words(&commands[0].words[1..], kernel);
+    let var_mem = kernel.get_varmem();
+    let saved_args: Vec<(String, Option<String>)> = {
+        let var_mem = var_mem.borrow();
+        let count = var_mem
+            .get("#")
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or(0)
+            .max(args.len());
+        std::iter::once(String::from("#"))
+            .chain((1..=count).map(|i| i.to_string()))
+            .map(|name| {
+                let value = var_mem.get(&name);
+                (name, value)
+            })
+            .collect()
+    };
+
+    let set_args = || -> Result<(), String> {
+        let mut var_mem = var_mem.borrow_mut();
+        var_mem.set(String::from("#"), args.len().to_string()).map_err(|e| e.to_string())?;
+        for (name, _) in saved_args.iter().skip(1) {
+            var_mem.unset(name);
+        }
+        for (i, arg) in args.iter().enumerate() {
+            var_mem.set((i + 1).to_string(), arg.clone()).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    };
+    if let Err(e) = set_args() {
+        restore_args(kernel, &saved_args);
+        return fail(kernel, e.as_str(), next)
+    }
+
+    job.frames.push(CallFrame{return_pc: next, saved_args});
+    kernel.set_status(SUCCESS);
+    header_pc + 1
+}
+
+/// Leaves the current function, back to the line after its call
+fn ret(kernel: &mut Kernel, job: &mut Job, next: usize) -> usize {
+    let Some(frame) = job.frames.pop() else {
+        return fail(kernel, "`return` outside of a function call", next)
+    };
+    restore_args(kernel, &frame.saved_args);
+    frame.return_pc
+}
+
+fn restore_args(kernel: &mut Kernel, saved_args: &[(String, Option<String>)]) {
+    let var_mem = kernel.get_varmem();
+    let mut var_mem = var_mem.borrow_mut();
+    for (name, _) in saved_args {
+        var_mem.unset(name);
+    }
+    for (name, value) in saved_args {
+        if let Some(value) = value {
+            if let Err(e) = var_mem.set(name.clone(), value.clone()) {
+                err_msg(&kernel.out, e.to_string().as_str());
+            }
+        }
+    }
+}
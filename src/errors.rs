@@ -2,9 +2,12 @@ use std::fmt;
 
 #[derive(Debug, Clone)]
 pub(crate) enum Exception {
+    #[allow(dead_code)] // Unused, the interpreter reports unknown commands itself
     UnknownCommand(String),
     IllegalMemoryAccess(usize),
-    IllegalKernelState(String)
+    IllegalKernelState(String),
+    PageFault(String), // The page couldn't be brought into memory
+    OutOfMemory(String),
 }
 
 impl fmt::Display for Exception {
@@ -13,6 +16,8 @@ impl fmt::Display for Exception {
             Exception::UnknownCommand(cmd) => write!(f, "unrecognized string: {}", cmd),
             Exception::IllegalMemoryAccess(addr) => write!(f, "illegal memory access at: {}", addr),
             Exception::IllegalKernelState(msg) => write!(f, "illegal kernel state: {}", msg),
+            Exception::PageFault(msg) => write!(f, "page fault: {}", msg),
+            Exception::OutOfMemory(msg) => write!(f, "out of memory: {}", msg),
         }
     }
 }
@@ -0,0 +1,879 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io,
+};
+use crate::blockdevice::{Block, BlockDevice, BLOCK_SIZE};
+
+pub(crate) type Ino = usize;
+
+pub(crate) const ROOT_INO: Ino = 0;
+
+const MAGIC: &[u8; 8] = b"MINOSFS2";
+const NUM_INODES: usize = 256;
+const INODE_SIZE: usize = 64;
+const INODES_PER_BLOCK: usize = BLOCK_SIZE / INODE_SIZE;
+const NUM_DIRECT: usize = 10;
+const PTRS_PER_BLOCK: usize = BLOCK_SIZE / 4;
+const MAX_FILE_BLOCKS: usize = NUM_DIRECT + PTRS_PER_BLOCK;
+const DIRENT_SIZE: usize = 32;
+const MAX_NAME_LEN: usize = DIRENT_SIZE - 5;
+
+#[derive(Debug, Clone)]
+pub(crate) enum FsError {
+    NotFound(String),
+    NotADirectory(String),
+    IsADirectory(String),
+    AlreadyExists(String),
+    DirectoryNotEmpty(String),
+    Busy(String),
+    InvalidName(String),
+    FileTooLarge(String),
+    NoSpace,
+    Corrupt(String),
+    OldVersion, // The image has the layout of an older minos
+    Io(String),
+}
+
+impl From<io::Error> for FsError {
+    fn from(e: io::Error) -> FsError {
+        FsError::Io(e.to_string())
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsError::NotFound(path) => write!(f, "{}: no such file or directory", path),
+            FsError::NotADirectory(path) => write!(f, "{}: not a directory", path),
+            FsError::IsADirectory(path) => write!(f, "{}: is a directory", path),
+            FsError::AlreadyExists(path) => write!(f, "{}: file exists", path),
+            FsError::DirectoryNotEmpty(path) => write!(f, "{}: directory not empty", path),
+            FsError::Busy(path) => write!(f, "{}: device or resource busy", path),
+            FsError::InvalidName(path) => write!(f, "{}: invalid file name", path),
+            FsError::FileTooLarge(path) => write!(f, "{}: file too large", path),
+            FsError::NoSpace => write!(f, "no space left on device"),
+            FsError::Corrupt(msg) => write!(f, "corrupt file system: {}", msg),
+            FsError::OldVersion => write!(f, "file system was made by an older version"),
+            FsError::Io(msg) => write!(f, "disk i/o error: {}", msg),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum InodeKind {
+    Free,
+    File,
+    Directory,
+}
+
+/// On-disk inode: kind, parent directory, size in bytes and the blocks holding the data.
+/// The first NUM_DIRECT blocks are pointed to directly, the rest through one indirect block.
+/// A pointer of 0 means no block, block 0 being the superblock.
+#[derive(Debug, Clone)]
+pub(crate) struct Inode {
+    pub(crate) kind: InodeKind,
+    pub(crate) parent: Ino,
+    pub(crate) size: usize,
+    direct: [u32; NUM_DIRECT],
+    indirect: u32,
+}
+
+impl Inode {
+    fn new(kind: InodeKind, parent: Ino) -> Inode {
+        Inode{
+            kind,
+            parent,
+            size: 0,
+            direct: [0; NUM_DIRECT],
+            indirect: 0,
+        }
+    }
+
+    fn decode(buf: &[u8]) -> Inode {
+        let kind = match buf[0] {
+            1 => InodeKind::File,
+            2 => InodeKind::Directory,
+            _ => InodeKind::Free,
+        };
+        let mut direct = [0; NUM_DIRECT];
+        for (i, ptr) in direct.iter_mut().enumerate() {
+            *ptr = read_u32(buf, 12 + 4 * i);
+        }
+        Inode{
+            kind,
+            parent: read_u32(buf, 4) as Ino,
+            size: read_u32(buf, 8) as usize,
+            direct,
+            indirect: read_u32(buf, 12 + 4 * NUM_DIRECT),
+        }
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf.fill(0);
+        buf[0] = match self.kind {
+            InodeKind::Free => 0,
+            InodeKind::File => 1,
+            InodeKind::Directory => 2,
+        };
+        write_u32(buf, 4, self.parent as u32);
+        write_u32(buf, 8, self.size as u32);
+        for (i, ptr) in self.direct.iter().enumerate() {
+            write_u32(buf, 12 + 4 * i, *ptr);
+        }
+        write_u32(buf, 12 + 4 * NUM_DIRECT, self.indirect);
+    }
+
+    fn num_blocks(&self) -> usize {
+        self.size.div_ceil(BLOCK_SIZE)
+    }
+}
+
+/// Where each region of the disk starts, as recorded in the superblock
+#[derive(Debug, Clone, Copy)]
+struct Superblock {
+    num_blocks: usize,
+    bitmap_start: usize,
+    inode_start: usize,
+    data_start: usize,
+}
+
+impl Superblock {
+    fn new(num_blocks: usize) -> Superblock {
+        let bitmap_blocks = num_blocks.div_ceil(BLOCK_SIZE * 8);
+        let inode_blocks = NUM_INODES.div_ceil(INODES_PER_BLOCK);
+        Superblock{
+            num_blocks,
+            bitmap_start: 1,
+            inode_start: 1 + bitmap_blocks,
+            data_start: 1 + bitmap_blocks + inode_blocks,
+        }
+    }
+
+    fn decode(buf: &Block) -> Result<Superblock, FsError> {
+        if &buf[0..8] != MAGIC {
+            // Images of older versions share the prefix but not the layout
+            if buf[0..7] == MAGIC[0..7] {
+                return Err(FsError::OldVersion)
+            }
+            return Err(FsError::Corrupt(String::from("bad magic number in superblock")))
+        }
+        if read_u32(buf, 8) as usize != BLOCK_SIZE {
+            return Err(FsError::Corrupt(String::from("block size mismatch")))
+        }
+        if read_u32(buf, 16) as usize != NUM_INODES {
+            return Err(FsError::Corrupt(String::from("inode count mismatch")))
+        }
+        Ok(Superblock{
+            num_blocks: read_u32(buf, 12) as usize,
+            bitmap_start: read_u32(buf, 20) as usize,
+            inode_start: read_u32(buf, 24) as usize,
+            data_start: read_u32(buf, 28) as usize,
+        })
+    }
+
+    fn encode(&self) -> Block {
+        let mut buf = [0; BLOCK_SIZE];
+        buf[0..8].copy_from_slice(MAGIC);
+        write_u32(&mut buf, 8, BLOCK_SIZE as u32);
+        write_u32(&mut buf, 12, self.num_blocks as u32);
+        write_u32(&mut buf, 16, NUM_INODES as u32);
+        write_u32(&mut buf, 20, self.bitmap_start as u32);
+        write_u32(&mut buf, 24, self.inode_start as u32);
+        write_u32(&mut buf, 28, self.data_start as u32);
+        buf
+    }
+
+    fn is_data_block(&self, block: u32) -> bool {
+        let block = block as usize;
+        block >= self.data_start && block < self.num_blocks
+    }
+}
+
+/// A hierarchical file system stored on a block device.
+///
+/// On-disk layout: block 0 is the superblock, followed by the free block bitmap,
+/// the inode table and finally the data blocks. Directories are files whose data
+/// is a packed array of fixed size entries, each an inode number and a name.
+pub(crate) struct FileSystem {
+    dev: BlockDevice,
+    sb: Superblock,
+    pub(crate) cwd: Ino,
+    cwd_path: String,
+}
+
+impl FileSystem {
+    /// Writes a fresh file system holding only the root directory onto a disk
+    pub(crate) fn format(dev: BlockDevice) -> Result<FileSystem, FsError> {
+        let sb = Superblock::new(dev.num_blocks());
+        let mut fs = FileSystem{
+            dev,
+            sb,
+            cwd: ROOT_INO,
+            cwd_path: String::from("/"),
+        };
+        fs.mkfs()?;
+        Ok(fs)
+    }
+
+    /// Loads the file system stored on a disk
+    pub(crate) fn mount(mut dev: BlockDevice) -> Result<FileSystem, FsError> {
+        let mut buf = [0; BLOCK_SIZE];
+        dev.read_block(0, &mut buf)?;
+        let sb = Superblock::decode(&buf)?;
+        if sb.num_blocks != dev.num_blocks() {
+            return Err(FsError::Corrupt(String::from("disk size does not match the superblock")))
+        }
+
+        let mut fs = FileSystem{
+            dev,
+            sb,
+            cwd: ROOT_INO,
+            cwd_path: String::from("/"),
+        };
+        if fs.read_inode(ROOT_INO)?.kind != InodeKind::Directory {
+            return Err(FsError::Corrupt(String::from("missing root directory")))
+        }
+        Ok(fs)
+    }
+
+    /// Wipes the disk's file system, leaving an empty root directory
+    pub(crate) fn mkfs(&mut self) -> Result<(), FsError> {
+        self.sb = Superblock::new(self.dev.num_blocks());
+        let zero = [0; BLOCK_SIZE];
+        for block in self.sb.bitmap_start..self.sb.data_start {
+            self.dev.write_block(block, &zero)?;
+        }
+        // Metadata blocks are never handed out
+        for block in 0..self.sb.data_start {
+            self.set_block_used(block, true)?;
+        }
+        self.write_inode(ROOT_INO, &Inode::new(InodeKind::Directory, ROOT_INO))?;
+        self.dev.write_block(0, &self.sb.encode())?;
+
+        self.cwd = ROOT_INO;
+        self.cwd_path = String::from("/");
+        Ok(())
+    }
+
+    pub(crate) fn read_inode(&mut self, ino: Ino) -> Result<Inode, FsError> {
+        if ino >= NUM_INODES {
+            return Err(FsError::Corrupt(format!("inode {ino} out of range")))
+        }
+        let mut buf = [0; BLOCK_SIZE];
+        self.dev.read_block(self.sb.inode_start + ino / INODES_PER_BLOCK, &mut buf)?;
+        let at = (ino % INODES_PER_BLOCK) * INODE_SIZE;
+        Ok(Inode::decode(&buf[at..at + INODE_SIZE]))
+    }
+
+    fn write_inode(&mut self, ino: Ino, inode: &Inode) -> Result<(), FsError> {
+        let block = self.sb.inode_start + ino / INODES_PER_BLOCK;
+        let mut buf = [0; BLOCK_SIZE];
+        self.dev.read_block(block, &mut buf)?;
+        let at = (ino % INODES_PER_BLOCK) * INODE_SIZE;
+        inode.encode(&mut buf[at..at + INODE_SIZE]);
+        self.dev.write_block(block, &buf)?;
+        Ok(())
+    }
+
+    fn alloc_inode(&mut self, inode: &Inode) -> Result<Ino, FsError> {
+        for ino in 0..NUM_INODES {
+            if self.read_inode(ino)?.kind == InodeKind::Free {
+                self.write_inode(ino, inode)?;
+                return Ok(ino)
+            }
+        }
+        Err(FsError::NoSpace)
+    }
+
+    fn is_block_used(&mut self, block: usize) -> Result<bool, FsError> {
+        let mut buf = [0; BLOCK_SIZE];
+        self.dev.read_block(self.sb.bitmap_start + block / (BLOCK_SIZE * 8), &mut buf)?;
+        let bit = block % (BLOCK_SIZE * 8);
+        Ok(buf[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    fn set_block_used(&mut self, block: usize, used: bool) -> Result<(), FsError> {
+        let bitmap_block = self.sb.bitmap_start + block / (BLOCK_SIZE * 8);
+        let mut buf = [0; BLOCK_SIZE];
+        self.dev.read_block(bitmap_block, &mut buf)?;
+        let bit = block % (BLOCK_SIZE * 8);
+        if used {
+            buf[bit / 8] |= 1 << (bit % 8);
+        } else {
+            buf[bit / 8] &= !(1 << (bit % 8));
+        }
+        self.dev.write_block(bitmap_block, &buf)?;
+        Ok(())
+    }
+
+    fn free_blocks(&mut self) -> Result<usize, FsError> {
+        let mut free = 0;
+        for block in self.sb.data_start..self.sb.num_blocks {
+            if !self.is_block_used(block)? {
+                free += 1;
+            }
+        }
+        Ok(free)
+    }
+
+    /// Takes the first free data block off the bitmap, zeroed
+    fn alloc_block(&mut self) -> Result<u32, FsError> {
+        for block in self.sb.data_start..self.sb.num_blocks {
+            if !self.is_block_used(block)? {
+                self.set_block_used(block, true)?;
+                self.dev.write_block(block, &[0; BLOCK_SIZE])?;
+                return Ok(block as u32)
+            }
+        }
+        Err(FsError::NoSpace)
+    }
+
+    /// The data blocks of an inode in file order
+    fn data_blocks(&mut self, inode: &Inode) -> Result<Vec<u32>, FsError> {
+        let count = inode.num_blocks();
+        let mut blocks: Vec<u32> = inode.direct.iter().copied().take(count).collect();
+        if count > NUM_DIRECT {
+            let mut buf = [0; BLOCK_SIZE];
+            self.dev.read_block(inode.indirect as usize, &mut buf)?;
+            blocks.extend((0..count - NUM_DIRECT).map(|i| read_u32(&buf, 4 * i)));
+        }
+        Ok(blocks)
+    }
+
+    /// Points an inode at its data blocks, allocating the indirect block if there isn't one
+    fn set_data_blocks(&mut self, inode: &mut Inode, blocks: &[u32]) -> Result<(), FsError> {
+        inode.direct = [0; NUM_DIRECT];
+        for (i, block) in blocks.iter().take(NUM_DIRECT).enumerate() {
+            inode.direct[i] = *block;
+        }
+        if blocks.len() > NUM_DIRECT {
+            if inode.indirect == 0 {
+                inode.indirect = self.alloc_block()?;
+            }
+            let mut buf = [0; BLOCK_SIZE];
+            for (i, block) in blocks[NUM_DIRECT..].iter().enumerate() {
+                write_u32(&mut buf, 4 * i, *block);
+            }
+            self.dev.write_block(inode.indirect as usize, &buf)?;
+        }
+        Ok(())
+    }
+
+    fn read_data(&mut self, inode: &Inode) -> Result<Vec<u8>, FsError> {
+        let mut data = Vec::with_capacity(inode.size);
+        let mut buf = [0; BLOCK_SIZE];
+        for block in self.data_blocks(inode)? {
+            self.dev.read_block(block as usize, &mut buf)?;
+            let n = (inode.size - data.len()).min(BLOCK_SIZE);
+            data.extend_from_slice(&buf[..n]);
+        }
+        Ok(data)
+    }
+
+    /// Replaces the contents of an inode, reallocating its blocks
+    fn write_data(&mut self, ino: Ino, data: &[u8]) -> Result<(), FsError> {
+        let mut inode = self.read_inode(ino)?;
+        let needed = data.len().div_ceil(BLOCK_SIZE);
+        if needed > MAX_FILE_BLOCKS {
+            return Err(FsError::FileTooLarge(format!("inode {ino}")))
+        }
+
+        let held = inode.num_blocks() + usize::from(inode.indirect != 0);
+        let wanted = needed + usize::from(needed > NUM_DIRECT);
+        if wanted > self.free_blocks()? + held {
+            return Err(FsError::NoSpace)
+        }
+
+        self.release_blocks(&mut inode)?;
+
+        let mut blocks: Vec<u32> = vec![];
+        for chunk in data.chunks(BLOCK_SIZE) {
+            let block = self.alloc_block()?;
+            let mut buf = [0; BLOCK_SIZE];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            self.dev.write_block(block as usize, &buf)?;
+            blocks.push(block);
+        }
+        self.set_data_blocks(&mut inode, &blocks)?;
+        inode.size = data.len();
+        self.write_inode(ino, &inode)
+    }
+
+    /// Gives an inode's blocks back to the bitmap and empties it
+    fn release_blocks(&mut self, inode: &mut Inode) -> Result<(), FsError> {
+        for block in self.data_blocks(inode)? {
+            self.set_block_used(block as usize, false)?;
+        }
+        if inode.indirect != 0 {
+            self.set_block_used(inode.indirect as usize, false)?;
+        }
+        inode.size = 0;
+        inode.direct = [0; NUM_DIRECT];
+        inode.indirect = 0;
+        Ok(())
+    }
+
+    fn dir_entries(&mut self, ino: Ino) -> Result<Vec<(String, Ino)>, FsError> {
+        let inode = self.read_inode(ino)?;
+        let data = self.read_data(&inode)?;
+        Ok(data.chunks(DIRENT_SIZE).map(decode_dirent).collect())
+    }
+
+    fn write_dir_entries(&mut self, ino: Ino, entries: &[(String, Ino)]) -> Result<(), FsError> {
+        let mut data = vec![0; entries.len() * DIRENT_SIZE];
+        for (chunk, (name, i)) in data.chunks_mut(DIRENT_SIZE).zip(entries) {
+            encode_dirent(chunk, name, *i);
+        }
+        self.write_data(ino, &data)
+    }
+
+    fn lookup(&mut self, dir: Ino, name: &str) -> Result<Option<Ino>, FsError> {
+        Ok(self.dir_entries(dir)?
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, ino)| ino))
+    }
+
+    /// Resolves an absolute or relative path to an inode, following `.` and `..`
+    pub(crate) fn resolve(&mut self, path: &str) -> Result<Ino, FsError> {
+        let mut ino = if path.starts_with('/') { ROOT_INO } else { self.cwd };
+
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            let dir = self.read_inode(ino)?;
+            if dir.kind != InodeKind::Directory {
+                return Err(FsError::NotADirectory(String::from(path)))
+            }
+            ino = match name {
+                "." => ino,
+                ".." => dir.parent,
+                _ => self.lookup(ino, name)?.ok_or(FsError::NotFound(String::from(path)))?,
+            };
+        }
+        Ok(ino)
+    }
+
+    /// Resolves the directory a path lives in, along with the name of its last component
+    fn resolve_parent(&mut self, path: &str) -> Result<(Ino, String), FsError> {
+        let trimmed = path.trim_end_matches('/');
+        let (dir, name) = match trimmed.rfind('/') {
+            Some(0) => ("/", &trimmed[1..]),
+            Some(idx) => (&trimmed[..idx], &trimmed[idx + 1..]),
+            None => (".", trimmed),
+        };
+
+        if name.is_empty() || name == "." || name == ".." || name.len() > MAX_NAME_LEN {
+            return Err(FsError::InvalidName(String::from(path)))
+        }
+
+        let parent = self.resolve(dir)?;
+        if self.read_inode(parent)?.kind != InodeKind::Directory {
+            return Err(FsError::NotADirectory(String::from(dir)))
+        }
+        Ok((parent, String::from(name)))
+    }
+
+    fn create(&mut self, path: &str, kind: InodeKind) -> Result<Ino, FsError> {
+        let (parent, name) = self.resolve_parent(path)?;
+        let mut entries = self.dir_entries(parent)?;
+        if entries.iter().any(|(n, _)| *n == name) {
+            return Err(FsError::AlreadyExists(String::from(path)))
+        }
+
+        let ino = self.alloc_inode(&Inode::new(kind, parent))?;
+        entries.push((name, ino));
+        if let Err(e) = self.write_dir_entries(parent, &entries) {
+            self.write_inode(ino, &Inode::new(InodeKind::Free, ROOT_INO))?;
+            return Err(e)
+        }
+        Ok(ino)
+    }
+
+    pub(crate) fn mkdir(&mut self, path: &str) -> Result<Ino, FsError> {
+        self.create(path, InodeKind::Directory)
+    }
+
+    /// Creates an empty file, an existing file is left untouched
+    pub(crate) fn touch(&mut self, path: &str) -> Result<Ino, FsError> {
+        match self.resolve(path) {
+            Ok(ino) => Ok(ino),
+            Err(FsError::NotFound(_)) => self.create(path, InodeKind::File),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Absolute path of whatever a path resolves to
+    pub(crate) fn canonicalize(&mut self, path: &str) -> Result<String, FsError> {
+        let ino = self.resolve(path)?;
+        let kind = self.read_inode(ino)?.kind;
+        if kind == InodeKind::Directory {
+            return self.path_of(ino)
+        }
+
+        // Files are found through their parent, they don't record their own name
+        let parent = self.read_inode(ino)?.parent;
+        let name = self.dir_entries(parent)?
+            .into_iter()
+            .find(|(_, i)| *i == ino)
+            .map(|(n, _)| n)
+            .ok_or(FsError::Corrupt(format!("file {ino} missing from its parent")))?;
+        let dir = self.path_of(parent)?;
+        Ok(format!("{}/{}", dir.trim_end_matches('/'), name))
+    }
+
+    pub(crate) fn is_dir(&mut self, path: &str) -> bool {
+        self.resolve(path)
+            .and_then(|ino| self.read_inode(ino))
+            .is_ok_and(|inode| inode.kind == InodeKind::Directory)
+    }
+
+    pub(crate) fn read_file(&mut self, path: &str) -> Result<Vec<u8>, FsError> {
+        let ino = self.resolve(path)?;
+        let inode = self.read_inode(ino)?;
+        if inode.kind == InodeKind::Directory {
+            return Err(FsError::IsADirectory(String::from(path)))
+        }
+        self.read_data(&inode)
+    }
+
+    /// Replaces the contents of a file, creating it if needed
+    pub(crate) fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        let ino = self.touch(path)?;
+        if self.read_inode(ino)?.kind == InodeKind::Directory {
+            return Err(FsError::IsADirectory(String::from(path)))
+        }
+        match self.write_data(ino, data) {
+            Err(FsError::FileTooLarge(_)) => Err(FsError::FileTooLarge(String::from(path))),
+            res => res,
+        }
+    }
+
+    fn unlink(&mut self, path: &str, kind: InodeKind) -> Result<(), FsError> {
+        let (parent, name) = self.resolve_parent(path)?;
+        let mut entries = self.dir_entries(parent)?;
+        let ino = entries
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, ino)| *ino)
+            .ok_or(FsError::NotFound(String::from(path)))?;
+
+        let mut inode = self.read_inode(ino)?;
+        match (kind, inode.kind) {
+            (InodeKind::File, InodeKind::Directory) => {
+                return Err(FsError::IsADirectory(String::from(path)))
+            }
+            (InodeKind::Directory, InodeKind::File) => {
+                return Err(FsError::NotADirectory(String::from(path)))
+            }
+            (InodeKind::Directory, InodeKind::Directory) => {
+                if inode.size != 0 {
+                    return Err(FsError::DirectoryNotEmpty(String::from(path)))
+                }
+                if ino == self.cwd {
+                    return Err(FsError::Busy(String::from(path)))
+                }
+            }
+            _ => {}
+        }
+
+        entries.retain(|(_, i)| *i != ino);
+        self.write_dir_entries(parent, &entries)?;
+        self.release_blocks(&mut inode)?;
+        self.write_inode(ino, &Inode::new(InodeKind::Free, ROOT_INO))
+    }
+
+    pub(crate) fn rm(&mut self, path: &str) -> Result<(), FsError> {
+        self.unlink(path, InodeKind::File)
+    }
+
+    pub(crate) fn rmdir(&mut self, path: &str) -> Result<(), FsError> {
+        self.unlink(path, InodeKind::Directory)
+    }
+
+    /// Lists a directory sorted by name, or a file by itself
+    pub(crate) fn ls(&mut self, path: &str) -> Result<Vec<(String, InodeKind)>, FsError> {
+        let ino = self.resolve(path)?;
+        if self.read_inode(ino)?.kind == InodeKind::File {
+            return Ok(vec![(String::from(path), InodeKind::File)])
+        }
+
+        let mut entries = vec![];
+        for (name, i) in self.dir_entries(ino)? {
+            entries.push((name, self.read_inode(i)?.kind));
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(entries)
+    }
+
+    pub(crate) fn cd(&mut self, path: &str) -> Result<(), FsError> {
+        let ino = self.resolve(path)?;
+        if self.read_inode(ino)?.kind != InodeKind::Directory {
+            return Err(FsError::NotADirectory(String::from(path)))
+        }
+        self.cwd_path = self.path_of(ino)?;
+        self.cwd = ino;
+        Ok(())
+    }
+
+    /// Absolute path of the current working directory
+    pub(crate) fn pwd(&self) -> String {
+        self.cwd_path.clone()
+    }
+
+    fn path_of(&mut self, mut ino: Ino) -> Result<String, FsError> {
+        let mut names: Vec<String> = vec![];
+        while ino != ROOT_INO {
+            let parent = self.read_inode(ino)?.parent;
+            let name = self.dir_entries(parent)?
+                .into_iter()
+                .find(|(_, i)| *i == ino)
+                .map(|(n, _)| n)
+                .ok_or(FsError::Corrupt(format!("directory {ino} missing from its parent")))?;
+            names.push(name);
+            ino = parent;
+        }
+        names.reverse();
+        Ok(format!("/{}", names.join("/")))
+    }
+
+    /// Checks the consistency of the on-disk structures, fixing what it finds if `repair` is set.
+    /// Returns a description of every problem found.
+    pub(crate) fn fsck(&mut self, repair: bool) -> Result<Vec<String>, FsError> {
+        let mut problems = vec![];
+        let mut owners: HashMap<u32, Ino> = HashMap::new();
+        let mut reachable = vec![false; NUM_INODES];
+        let mut stack = vec![ROOT_INO];
+        reachable[ROOT_INO] = true;
+        // Repairs allocate blocks while the walk is still claiming them, so every block
+        // an inode points at is taken out of the free pool until the walk is done
+        let reserved = if repair { self.reserve_referenced_blocks()? } else { HashSet::new() };
+
+        // Walk the tree from the root, claiming every block along the way
+        while let Some(ino) = stack.pop() {
+            let mut inode = self.read_inode(ino)?;
+            let bad_blocks = self.check_blocks(ino, &mut inode, &mut owners, repair)?;
+            let unreadable = !bad_blocks.is_empty() && !repair;
+            problems.extend(bad_blocks);
+
+            if inode.kind != InodeKind::Directory {
+                continue
+            }
+            if unreadable {
+                problems.push(format!("skipped the entries of directory inode {ino}"));
+                continue
+            }
+
+            let entries = self.dir_entries(ino)?;
+            let mut kept = vec![];
+            for (name, child) in entries.iter().cloned() {
+                if child >= NUM_INODES || self.read_inode(child)?.kind == InodeKind::Free {
+                    problems.push(format!("dangling directory entry {name} in inode {ino} points to inode {child}"));
+                    continue
+                }
+                if reachable[child] {
+                    problems.push(format!("directory entry {name} in inode {ino} links inode {child} a second time"));
+                    continue
+                }
+                reachable[child] = true;
+
+                let mut child_inode = self.read_inode(child)?;
+                if child_inode.kind == InodeKind::Directory && child_inode.parent != ino {
+                    problems.push(format!("directory inode {child} has parent {} instead of {ino}", child_inode.parent));
+                    if repair {
+                        child_inode.parent = ino;
+                        self.write_inode(child, &child_inode)?;
+                    }
+                }
+                stack.push(child);
+                kept.push((name, child));
+            }
+
+            if repair && kept.len() != entries.len() {
+                // Rewriting the directory moves it to new blocks, claim those instead
+                self.write_dir_entries(ino, &kept)?;
+                owners.retain(|_, owner| *owner != ino);
+                let inode = self.read_inode(ino)?;
+                for block in self.data_blocks(&inode)? {
+                    owners.insert(block, ino);
+                }
+                if inode.num_blocks() > NUM_DIRECT {
+                    owners.insert(inode.indirect, ino);
+                }
+            }
+        }
+
+        for (ino, seen) in reachable.iter().enumerate() {
+            if !seen && self.read_inode(ino)?.kind != InodeKind::Free {
+                problems.push(format!("orphaned inode {ino}"));
+                if repair {
+                    self.write_inode(ino, &Inode::new(InodeKind::Free, ROOT_INO))?;
+                }
+            }
+        }
+
+        for block in self.sb.data_start..self.sb.num_blocks {
+            let reserved = reserved.contains(&(block as u32));
+            let used = self.is_block_used(block)? && !reserved;
+            let owned = owners.contains_key(&(block as u32));
+            if reserved && !owned {
+                self.set_block_used(block, false)?;
+            } else if used && !owned {
+                problems.push(format!("leaked block {block}"));
+                if repair {
+                    self.set_block_used(block, false)?;
+                }
+            } else if owned && !used {
+                problems.push(format!("block {block} is in use but marked free"));
+                if repair {
+                    self.set_block_used(block, true)?;
+                }
+            }
+        }
+        Ok(problems)
+    }
+
+    /// Marks every data block pointed at by an inode in use, returns the ones that were free
+    fn reserve_referenced_blocks(&mut self) -> Result<HashSet<u32>, FsError> {
+        let mut referenced = vec![];
+        for ino in 0..NUM_INODES {
+            let inode = self.read_inode(ino)?;
+            if inode.kind == InodeKind::Free {
+                continue
+            }
+            // Pointers are taken as they are, the walk is what checks them
+            let count = inode.num_blocks().min(MAX_FILE_BLOCKS);
+            referenced.extend(inode.direct.iter().copied().take(count));
+            if count > NUM_DIRECT && self.sb.is_data_block(inode.indirect) {
+                let mut buf = [0; BLOCK_SIZE];
+                self.dev.read_block(inode.indirect as usize, &mut buf)?;
+                referenced.push(inode.indirect);
+                referenced.extend((0..count - NUM_DIRECT).map(|i| read_u32(&buf, 4 * i)));
+            }
+        }
+
+        let mut reserved = HashSet::new();
+        for block in referenced {
+            if self.sb.is_data_block(block) && !self.is_block_used(block as usize)? {
+                self.set_block_used(block as usize, true)?;
+                reserved.insert(block);
+            }
+        }
+        Ok(reserved)
+    }
+
+    /// Claims the blocks of an inode, reporting pointers outside the data region and blocks
+    /// already claimed by another inode. Repairs cut the file at a bad pointer and give
+    /// the inode its own copy of a cross-linked block.
+    fn check_blocks(
+        &mut self,
+        ino: Ino,
+        inode: &mut Inode,
+        owners: &mut HashMap<u32, Ino>,
+        repair: bool,
+    ) -> Result<Vec<String>, FsError> {
+        let mut problems = vec![];
+
+        if inode.num_blocks() > MAX_FILE_BLOCKS {
+            problems.push(format!("inode {ino} has a size of {} bytes, past the largest file", inode.size));
+            inode.size = MAX_FILE_BLOCKS * BLOCK_SIZE;
+        }
+        if inode.num_blocks() > NUM_DIRECT && !self.sb.is_data_block(inode.indirect) {
+            problems.push(format!("inode {ino} has a bad indirect block {}", inode.indirect));
+            inode.size = NUM_DIRECT * BLOCK_SIZE;
+            inode.indirect = 0;
+        }
+
+        let mut blocks = self.data_blocks(inode)?;
+        if inode.num_blocks() > NUM_DIRECT {
+            match owners.get(&inode.indirect) {
+                Some(other) => {
+                    problems.push(format!("indirect block {} is cross-linked between inodes {other} and {ino}", inode.indirect));
+                    // A fresh indirect block gets allocated when the pointers are written back
+                    inode.indirect = 0;
+                }
+                None => { owners.insert(inode.indirect, ino); }
+            }
+        }
+
+        for i in 0..blocks.len() {
+            let block = blocks[i];
+            if !self.sb.is_data_block(block) {
+                problems.push(format!("inode {ino} has a bad block pointer {block}"));
+                // The file ends right before the bad block
+                inode.size = inode.size.min(i * BLOCK_SIZE);
+                blocks.truncate(i);
+                break
+            }
+            match owners.get(&block) {
+                Some(other) => {
+                    problems.push(format!("block {block} is cross-linked between inodes {other} and {ino}"));
+                    if repair {
+                        let copy = self.alloc_block()?;
+                        let mut buf = [0; BLOCK_SIZE];
+                        self.dev.read_block(block as usize, &mut buf)?;
+                        self.dev.write_block(copy as usize, &buf)?;
+                        blocks[i] = copy;
+                        owners.insert(copy, ino);
+                    }
+                }
+                None => { owners.insert(block, ino); }
+            }
+        }
+
+        if repair && !problems.is_empty() {
+            self.set_data_blocks(inode, &blocks)?;
+            if inode.num_blocks() > NUM_DIRECT {
+                owners.insert(inode.indirect, ino);
+            }
+            self.write_inode(ino, inode)?;
+        }
+        Ok(problems)
+    }
+}
+
+/// Damage done on purpose so the tests can check what fsck makes of it
+#[cfg(test)]
+impl FileSystem {
+    /// `crosslink A B` points the first block of B at the first block of A, `leak` marks a free
+    /// block used and `dangle PATH` frees the inode of PATH but leaves its directory entry
+    pub(crate) fn damage(&mut self, what: &[&str]) -> Result<(), FsError> {
+        match what {
+            ["crosslink", a, b] => {
+                let ino = self.resolve(a)?;
+                let block = self.read_inode(ino)?.direct[0];
+                let ino = self.resolve(b)?;
+                let mut inode = self.read_inode(ino)?;
+                inode.direct[0] = block;
+                self.write_inode(ino, &inode)
+            }
+            ["leak"] => self.alloc_block().map(|_| ()),
+            ["dangle", path] => {
+                let ino = self.resolve(path)?;
+                self.write_inode(ino, &Inode::new(InodeKind::Free, ROOT_INO))
+            }
+            _ => panic!("Unknown damage {what:?}"),
+        }
+    }
+}
+
+fn read_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn write_u32(buf: &mut [u8], at: usize, val: u32) {
+    buf[at..at + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+/// A directory entry is the inode number, the name length and the name padded with zeroes
+fn decode_dirent(buf: &[u8]) -> (String, Ino) {
+    let ino = read_u32(buf, 0) as Ino;
+    let len = (buf[4] as usize).min(MAX_NAME_LEN);
+    let name = String::from_utf8_lossy(&buf[5..5 + len]).into_owned();
+    (name, ino)
+}
+
+fn encode_dirent(buf: &mut [u8], name: &str, ino: Ino) {
+    write_u32(buf, 0, ino as u32);
+    buf[4] = name.len() as u8;
+    buf[5..5 + name.len()].copy_from_slice(name.as_bytes());
+}
@@ -0,0 +1,219 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+};
+
+pub(crate) const ALLOCATORS: [&str; 3] = ["FIRST", "BEST", "BUDDY"];
+pub(crate) const DEFAULT_ALLOCATOR: &str = "FIRST";
+
+/// Hands out blocks of lines from a heap, addresses are offsets in the heap
+pub(crate) trait Allocator: fmt::Debug {
+    fn name(&self) -> &'static str;
+    /// Reserves a block of at least `n` lines, returns its start and actual size
+    fn alloc(&mut self, n: usize) -> Option<(usize, usize)>;
+    /// Gives back a block returned by `alloc`
+    fn free(&mut self, start: usize, size: usize);
+    /// Free blocks as (start, size), in address order
+    fn free_blocks(&self) -> Vec<(usize, usize)>;
+}
+
+pub(crate) fn new_allocator(name: &str, size: usize) -> Option<Box<dyn Allocator>> {
+    match name {
+        "FIRST" => Some(Box::new(FreeList::new(size, false))),
+        "BEST" => Some(Box::new(FreeList::new(size, true))),
+        "BUDDY" => Some(Box::new(Buddy::new(size))),
+        _ => None
+    }
+}
+
+/// A list of free blocks sorted by address, neighbours are merged when a block is freed.
+/// First fit takes the first block large enough, best fit the smallest one.
+#[derive(Debug)]
+pub(crate) struct FreeList {
+    blocks: Vec<(usize, usize)>,
+    best_fit: bool,
+}
+
+impl FreeList {
+    pub(crate) fn new(size: usize, best_fit: bool) -> FreeList {
+        FreeList{
+            blocks: vec![(0, size)],
+            best_fit,
+        }
+    }
+}
+
+impl Allocator for FreeList {
+    fn name(&self) -> &'static str {
+        if self.best_fit { "BEST" } else { "FIRST" }
+    }
+
+    fn alloc(&mut self, n: usize) -> Option<(usize, usize)> {
+        let mut fits = self.blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, size))| *size >= n);
+        let (idx, &(start, size)) = if self.best_fit {
+            fits.min_by_key(|(_, (_, size))| *size)?
+        } else {
+            fits.next()?
+        };
+
+        if size == n {
+            self.blocks.remove(idx);
+        } else {
+            self.blocks[idx] = (start + n, size - n);
+        }
+        Some((start, n))
+    }
+
+    fn free(&mut self, start: usize, size: usize) {
+        let idx = self.blocks.partition_point(|(s, _)| *s < start);
+        self.blocks.insert(idx, (start, size));
+
+        // Merge with the next block first so that the index stays valid
+        if let Some(&(next_start, next_size)) = self.blocks.get(idx + 1) {
+            if start + size == next_start {
+                self.blocks[idx].1 += next_size;
+                self.blocks.remove(idx + 1);
+            }
+        }
+        if idx > 0 {
+            let (prev_start, prev_size) = self.blocks[idx - 1];
+            if prev_start + prev_size == start {
+                self.blocks[idx - 1].1 += self.blocks[idx].1;
+                self.blocks.remove(idx);
+            }
+        }
+    }
+
+    fn free_blocks(&self) -> Vec<(usize, usize)> {
+        self.blocks.clone()
+    }
+}
+
+/// Binary buddy allocator, blocks are powers of two and freed blocks merge with their buddy
+#[derive(Debug)]
+pub(crate) struct Buddy {
+    free: Vec<Vec<usize>>, // Start of the free blocks of size 2^order, by order
+}
+
+impl Buddy {
+    /// Only the largest power of two that fits in `size` is managed
+    pub(crate) fn new(size: usize) -> Buddy {
+        let max_order = size.max(1).ilog2() as usize;
+        let mut free = vec![vec![]; max_order + 1];
+        if size > 0 {
+            free[max_order].push(0);
+        }
+        Buddy{free}
+    }
+}
+
+impl Allocator for Buddy {
+    fn name(&self) -> &'static str { "BUDDY" }
+
+    fn alloc(&mut self, n: usize) -> Option<(usize, usize)> {
+        let order = n.max(1).next_power_of_two().ilog2() as usize;
+        let mut k = (order..self.free.len()).find(|k| !self.free[*k].is_empty())?;
+
+        // Take the lowest block and split it down to the size asked for
+        let blocks = &mut self.free[k];
+        let lowest = blocks.iter().enumerate().min_by_key(|(_, s)| **s).map(|(i, _)| i)?;
+        let start = blocks.swap_remove(lowest);
+        while k > order {
+            k -= 1;
+            self.free[k].push(start + (1 << k));
+        }
+        Some((start, 1 << order))
+    }
+
+    fn free(&mut self, start: usize, size: usize) {
+        let (mut start, mut order) = (start, size.ilog2() as usize);
+        while order + 1 < self.free.len() {
+            let buddy = start ^ (1 << order);
+            let Some(pos) = self.free[order].iter().position(|s| *s == buddy) else {
+                break
+            };
+            self.free[order].swap_remove(pos);
+            start = start.min(buddy);
+            order += 1;
+        }
+        self.free[order].push(start);
+    }
+
+    fn free_blocks(&self) -> Vec<(usize, usize)> {
+        let mut blocks: Vec<(usize, usize)> = self.free
+            .iter()
+            .enumerate()
+            .flat_map(|(order, starts)| starts.iter().map(move |s| (*s, 1 << order)))
+            .collect();
+        blocks.sort();
+        blocks
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Allocation {
+    pub(crate) start: usize,
+    pub(crate) requested: usize,
+    pub(crate) size: usize, // What the allocator actually reserved
+}
+
+/// A process's heap, it spans the whole data segment
+#[derive(Debug)]
+pub(crate) struct Heap {
+    pub(crate) size: usize,
+    pub(crate) allocator: Box<dyn Allocator>,
+    pub(crate) allocations: BTreeMap<String, Allocation>,
+}
+
+impl Heap {
+    pub(crate) fn new(allocator: &str, size: usize) -> Option<Heap> {
+        Some(Heap{
+            size,
+            allocator: new_allocator(allocator, size)?,
+            allocations: BTreeMap::new(),
+        })
+    }
+
+    pub(crate) fn alloc(&mut self, name: &str, n: usize) -> Result<Allocation, String> {
+        if self.allocations.contains_key(name) {
+            return Err(format!("{name} is already allocated"))
+        }
+        if n == 0 {
+            return Err(String::from("can't allocate 0 lines"))
+        }
+        let (start, size) = self.allocator
+            .alloc(n)
+            .ok_or(format!("out of heap memory, no free block of {n} lines"))?;
+
+        let allocation = Allocation{start, requested: n, size};
+        self.allocations.insert(String::from(name), allocation);
+        Ok(allocation)
+    }
+
+    pub(crate) fn free(&mut self, name: &str) -> Result<(), String> {
+        let allocation = self.allocations
+            .remove(name)
+            .ok_or(format!("{name} is not allocated"))?;
+        self.allocator.free(allocation.start, allocation.size);
+        Ok(())
+    }
+
+    /// Lines lost inside allocated blocks, to rounding up by the allocator
+    pub(crate) fn internal_fragmentation(&self) -> usize {
+        self.allocations.values().map(|a| a.size - a.requested).sum()
+    }
+
+    /// Share of the free lines that are outside the largest free block, so can't serve a large request
+    pub(crate) fn external_fragmentation(&self) -> f64 {
+        let blocks = self.allocator.free_blocks();
+        let free: usize = blocks.iter().map(|(_, size)| size).sum();
+        let largest = blocks.iter().map(|(_, size)| *size).max().unwrap_or(0);
+        if free == 0 {
+            return 0.0
+        }
+        1.0 - largest as f64 / free as f64
+    }
+}
@@ -1,199 +1,959 @@
-use crate::shellmemory::{VarMemory, ProgMemory, FrameTable};
+use crate::shellmemory::{VarMemory, NUM_FRAMES, REFERENCE_WINDOW};
+use crate::filesystem::{FileSystem, InodeKind};
+use crate::replacement::{new_policy, simulate, POLICIES};
+use crate::pagetable::MAX_PAGE_TABLE_LEVELS;
+use crate::tlb::{Tlb, TlbPolicy};
+use crate::heap::{Heap, ALLOCATORS};
+use crate::segment::{SegmentKind, DATA_SEGMENT_SIZE, STACK_SEGMENT_SIZE};
+use crate::output::{outln, Output};
+use crate::parser::{parse, Command, Connector, Word};
+use crate::job::Job;
+use crate::proctable::ProcState;
 use crate::kernel::{Kernel, Mode};
-use std::fs::{File};
-use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use crate::job;
-use crate::job::FailProgramCreation;
 
-fn bad_cmd(input: &str) {
-    println!("minsh: unrecognized command: {input}");
-    return;
+/// Exit status of a command, 0 when it succeeded
+pub(crate) type Status = i32;
+
+pub(crate) const SUCCESS: Status = 0;
+pub(crate) const FAILURE: Status = 1;
+
+fn bad_cmd(out: &Output, input: &str) -> Status {
+    outln!(out, "minsh: unrecognized command: {input}");
+    FAILURE
 }
 
-pub(crate) fn err_msg(input: &str) {
-    println!("minsh: err: {input}");
-    return;
+pub(crate) fn err_msg(out: &Output, input: &str) -> Status {
+    outln!(out, "minsh: err: {input}");
+    FAILURE
 }
 
+/// Runs a line of commands, `&&` and `||` short-circuit on the status of the command before.
+/// Returns the status of the last command that ran.
 pub fn interpreter(
-    input: Vec<String>,
+    line: &str,
     kernel: &mut Kernel,
-) {
+) -> Status {
+    match parse(line) {
+        Ok(commands) => run_commands(&commands, kernel),
+        Err(e) => err_msg(&kernel.out, e.as_str()),
+    }
+}
 
-    for arg in input {
-        if arg.trim().is_empty()  {
-            return;
+/// Runs parsed commands, see `interpreter`
+pub(crate) fn run_commands(commands: &[Command], kernel: &mut Kernel) -> Status {
+    let mut status = SUCCESS;
+    for command in commands {
+        let run = match command.connector {
+            Connector::Seq => true,
+            Connector::And => status == SUCCESS,
+            Connector::Or => status != SUCCESS,
+        };
+        if !run {
+            continue
+        }
+        
+        // Expanded right before running, so a command sees variables set earlier on the line
+        let arg_arr = expand_words(&command.words, kernel);
+        if arg_arr.is_empty() {
+            continue
+        }
+        status = run_command(&arg_arr, command.background, kernel);
+        kernel.set_status(status);
+        
+        // Nothing else on the line runs once the process exits
+        if kernel.exit_requested.is_some() {
+            break
         }
+    }
+    status
+}
 
-        // split by ; for multi-cmd processing
-        let arg_arr: Vec<String> = arg.split_whitespace().map(str::to_string).collect();
+/// Substitutes the variables of the current process in words, dropping those that expand to nothing
+pub(crate) fn expand_words(words: &[Word], kernel: &Kernel) -> Vec<String> {
+    let var_mem = kernel.get_varmem();
+    let var_mem = var_mem.borrow();
+    let last = kernel.last_status();
+    words.iter().filter_map(|w| w.expand(&var_mem, last)).collect()
+}
 
-        // as_str() does not consume anything, only returns str slice
-        match arg_arr[0].as_str() {
-            "echo" => {
-                if arg_arr.len() > 2 {
-                    bad_cmd(arg_arr.join(" ").as_str());
-                    return
-                }
-                echo(kernel.get_mut_varmem(),&arg_arr[1])
-            },
-            "set" => {
-                if arg_arr.len() < 3 {
-                    bad_cmd("usage: set <VAR> <VALUE>");
-                    return
-                }
-                set(kernel.get_mut_varmem(), &arg_arr[1..])
-            },
-            "exec" => {
-                if arg_arr.len() < 2 {
-                    bad_cmd("usage: exec <FILENAME 1> <FILENAME 2> <etc...>");
-                    return
-                }
-                exec(&arg_arr[1..], kernel)
-            },
-            "cat" => {
-                if arg_arr.len() < 2 {
-                    bad_cmd("usage: cat <FILENAME>");
-                    return
-                }
-                cat(&arg_arr[1])
-            },
-            "setmod" => {
-                if arg_arr.len() < 2 {
-                    bad_cmd("usage: setmod <FCFS, RR, SJF>");
-                    return
-                }
-                setmod(&arg_arr[1], kernel)
+fn run_command(arg_arr: &[String], background: bool, kernel: &mut Kernel) -> Status {
+    let out = kernel.out.clone();
+    // as_str() does not consume anything, only returns str slice
+    match arg_arr[0].as_str() {
+        "echo" => echo(&arg_arr[1..], &out),
+        "exit" => {
+            if arg_arr.len() > 2 {
+                return bad_cmd(&out, "usage: exit [STATUS]")
+            }
+            exit(arg_arr.get(1), kernel)
+        },
+        "set" => {
+            if arg_arr.len() < 3 {
+                return bad_cmd(&out, "usage: set <VAR> <VALUE>")
+            }
+            set(&mut kernel.get_varmem().borrow_mut(), &arg_arr[1..], &out)
+        },
+        "unset" => {
+            if arg_arr.len() < 2 {
+                return bad_cmd(&out, "usage: unset <VAR 1> <VAR 2> <etc...>")
+            }
+            unset(&mut kernel.get_varmem().borrow_mut(), &arg_arr[1..], &out)
+        },
+        "exec" => {
+            if arg_arr.len() < 2 {
+                return bad_cmd(&out, "usage: exec <FILENAME 1> <FILENAME 2> <etc...> [MODE] [&]")
+            }
+            exec(&arg_arr[1..], background, kernel)
+        },
+        "cat" => {
+            if arg_arr.len() < 2 {
+                return bad_cmd(&out, "usage: cat <FILENAME>")
             }
-            _ => bad_cmd(arg.as_str())
-            
+            cat(&arg_arr[1], kernel.get_mut_fs(), &out)
+        },
+        "run" => {
+            if arg_arr.len() != 2 {
+                return bad_cmd(&out, "usage: run <FILENAME>")
+            }
+            exec(&arg_arr[1..], false, kernel)
+        },
+        "import" => {
+            if arg_arr.len() != 3 {
+                return bad_cmd(&out, "usage: import <HOST PATH> <PATH>")
+            }
+            import(&arg_arr[1], &arg_arr[2], kernel.get_mut_fs(), &out)
+        },
+        "hostexport" => {
+            if arg_arr.len() != 3 {
+                return bad_cmd(&out, "usage: hostexport <PATH> <HOST PATH>")
+            }
+            host_export(&arg_arr[1], &arg_arr[2], kernel.get_mut_fs(), &out)
+        },
+        "export" => {
+            if arg_arr.len() != 2 {
+                return bad_cmd(&out, "usage: export <VAR>[=<VALUE>]")
+            }
+            export_var(&arg_arr[1], &mut kernel.get_varmem().borrow_mut(), &out)
+        },
+        "setmod" => {
+            if arg_arr.len() < 2 {
+                return bad_cmd(&out, "usage: setmod <FCFS, RR [TIME SLICE], SJF, AGING [TIME SLICE]>")
+            }
+            setmod(&arg_arr[1..], kernel)
         }
+        "setpolicy" => {
+            if arg_arr.len() < 2 {
+                return bad_cmd(&out, "usage: setpolicy <FIFO, LRU, CLOCK, LFU>")
+            }
+            setpolicy(&arg_arr[1], kernel)
+        }
+        "ps" => ps(kernel),
+        "jobs" => jobs(kernel),
+        "fg" => {
+            if arg_arr.len() > 2 {
+                return bad_cmd(&out, "usage: fg [PID]")
+            }
+            fg(&arg_arr[1..], kernel)
+        },
+        "wait" => wait(&arg_arr[1..], kernel),
+        "kill" => {
+            if arg_arr.len() < 2 {
+                return bad_cmd(&out, "usage: kill <PID 1> <PID 2> <etc...>")
+            }
+            kill(&arg_arr[1..], kernel)
+        },
+        "pagestat" => pagestat(kernel),
+        "memdump" => match kernel.memory_dump() {
+            Ok(()) => SUCCESS,
+            Err(e) => err_msg(&out, e.to_string().as_str())
+        },
+        "pagetable" => {
+            if arg_arr.len() != 2 {
+                return bad_cmd(&out, "usage: pagetable <PID>")
+            }
+            pagetable(&arg_arr[1], kernel)
+        },
+        "tlbstat" => tlbstat(kernel.get_mut_tlb(), &out),
+        "settlb" => {
+            if !(3..=5).contains(&arg_arr.len()) {
+                return bad_cmd(&out, "usage: settlb <ENTRIES> <WAYS> [FIFO, LRU] [FLUSH, TAGGED]")
+            }
+            settlb(&arg_arr[1..], kernel)
+        },
+        "alloc" => {
+            if arg_arr.len() != 3 {
+                return bad_cmd(&out, "usage: alloc <NAME> <LINES>")
+            }
+            alloc(&arg_arr[1], &arg_arr[2], kernel)
+        },
+        "free" => {
+            if arg_arr.len() != 2 {
+                return bad_cmd(&out, "usage: free <NAME>")
+            }
+            free(&arg_arr[1], kernel)
+        },
+        "push" => {
+            if arg_arr.len() < 2 {
+                return bad_cmd(&out, "usage: push <VALUE>")
+            }
+            push(arg_arr[1..].join(" "), kernel)
+        },
+        "pop" => pop(true, kernel),
+        "peek" => pop(false, kernel),
+        "heapstat" => heapstat(kernel),
+        "setalloc" => {
+            if arg_arr.len() != 2 {
+                return bad_cmd(&out, "usage: setalloc <FIRST, BEST, BUDDY>")
+            }
+            setalloc(&arg_arr[1], kernel)
+        },
+        "setlevels" => {
+            if arg_arr.len() != 2 {
+                return bad_cmd(&out, "usage: setlevels <PAGE TABLE LEVELS>")
+            }
+            setlevels(&arg_arr[1], kernel)
+        },
+        "ls" => {
+            let path = arg_arr.get(1).map_or(".", String::as_str);
+            ls(path, kernel.get_mut_fs(), &out)
+        },
+        "cd" => {
+            let path = arg_arr.get(1).map_or("/", String::as_str);
+            cd(path, kernel.get_mut_fs(), &out)
+        },
+        "mkfs" => {
+            let res = kernel.get_mut_fs()
+                .mkfs()
+                .and_then(|_| kernel.init_backing_store());
+            match res {
+                Ok(()) => {
+                    outln!(out, "Formatted disk, all files were erased");
+                    SUCCESS
+                }
+                Err(e) => err_msg(&out, e.to_string().as_str())
+            }
+        },
+        "fsck" => {
+            let repair = arg_arr.get(1).is_some_and(|a| a == "-r");
+            fsck(repair, kernel.get_mut_fs(), &out)
+        },
+        "pwd" => {
+            outln!(out, "{}", kernel.get_mut_fs().pwd());
+            SUCCESS
+        },
+        "mkdir" => {
+            if arg_arr.len() < 2 {
+                return bad_cmd(&out, "usage: mkdir <DIRECTORY 1> <DIRECTORY 2> <etc...>")
+            }
+            mkdir(&arg_arr[1..], kernel.get_mut_fs(), &out)
+        },
+        "touch" => {
+            if arg_arr.len() < 2 {
+                return bad_cmd(&out, "usage: touch <FILENAME 1> <FILENAME 2> <etc...>")
+            }
+            touch(&arg_arr[1..], kernel.get_mut_fs(), &out)
+        },
+        "rm" => {
+            if arg_arr.len() < 2 {
+                return bad_cmd(&out, "usage: rm <FILENAME 1> <FILENAME 2> <etc...>")
+            }
+            rm(&arg_arr[1..], kernel.get_mut_fs(), &out)
+        },
+        "rmdir" => {
+            if arg_arr.len() < 2 {
+                return bad_cmd(&out, "usage: rmdir <DIRECTORY 1> <DIRECTORY 2> <etc...>")
+            }
+            rmdir(&arg_arr[1..], kernel.get_mut_fs(), &out)
+        },
+        "if" | "then" | "else" | "fi" | "while" | "for" | "do" | "done" | "function" | "}" | "return" => {
+            err_msg(&out, format!("`{}` can only be used in a script", arg_arr[0]).as_str())
+        },
+        _ => bad_cmd(&out, arg_arr.join(" ").as_str())
     }
 }
 
-fn echo(var_mem: &VarMemory, input: &str) {
-    if let Some(val) = var_mem.get(input) {
-        let res = val.as_str();
-        println!("{res}");
-    } else {
-        println!("{input}");
-    }
+fn echo(input: &[String], out: &Output) -> Status {
+    outln!(out, "{}", input.join(" "));
+    SUCCESS
 }
 
-fn set(var_mem: &mut VarMemory, input: &[String]) {
+/// Ends the current process, a script's job or the shell itself, with the given status or the last one
+fn exit(status: Option<&String>, kernel: &mut Kernel) -> Status {
+    let status = match status {
+        Some(s) => match s.parse::<Status>() {
+            Ok(n) => n,
+            Err(_) => return err_msg(&kernel.out, format!("invalid exit status: {s}").as_str())
+        },
+        None => kernel.last_status(),
+    };
+    kernel.exit_requested = Some(status);
+    status
+}
+
+fn set(var_mem: &mut VarMemory, input: &[String], out: &Output) -> Status {
     if input.len() < 2 {
-        bad_cmd("usage: set <VAR> <VALUE>")
+        return bad_cmd(out, "usage: set <VAR> <VALUE>")
+    }
+    // Everything after the name is the value, so `set x hello world` keeps both words
+    match var_mem.set(input[0].clone(), input[1..].join(" ")) {
+        Ok(()) => SUCCESS,
+        Err(e) => err_msg(out, e.to_string().as_str())
     }
-    var_mem.set(input[0].clone(), input[1].clone())
 }
 
+fn unset(var_mem: &mut VarMemory, keys: &[String], out: &Output) -> Status {
+    let mut status = SUCCESS;
+    for key in keys {
+        if !var_mem.unset(key) {
+            status = err_msg(out, format!("no variable named {key}").as_str());
+        }
+    }
+    status
+}
+
+/// Exports a variable of the current process to the processes it starts, `VAR=VALUE` sets it first
+fn export_var(input: &str, var_mem: &mut VarMemory, out: &Output) -> Status {
+    let key = match input.split_once('=') {
+        Some((key, val)) => {
+            if let Err(e) = var_mem.set(String::from(key), String::from(val)) {
+                return err_msg(out, e.to_string().as_str())
+            }
+            key
+        }
+        None => input,
+    };
+    if !var_mem.export(key) {
+        return err_msg(out, format!("no variable named {key}").as_str())
+    }
+    SUCCESS
+}
+
+/// Runs programs, `exec PROG... [FCFS, SJF, RR, AGING] [&]`. A scheduler mode given after the programs
+/// becomes the current one. Without `&` the programs are waited for and the status is the exit status
+/// of the last one given, with `&` they run in the background while the shell goes on.
+/// The parser tells whether the command ended with `&`, or with a lone `#` like in the course's shell.
 fn exec(
-    filenames: &[String], 
+    args: &[String], 
+    background: bool,
     kern: &mut Kernel,
-) {
-    for file in filenames {
-        let prog_res = job::Program::new(
-            kern,
-            file
-        );
-        match prog_res {
-            Ok(p) => {
-                let job = job::Job::new(
-                    Some(p.size),
-                    str::to_string(file),
-                    Some(p),
-                    kern,
-                );
-                if let Ok(j) = job {
-                    kern.queue_job(j);
-                } else {
-                    err_msg(job.unwrap_err());
-                    return
-                }
-            },
-            Err(e) => match e {
-                FailProgramCreation::Error(e) => { 
-                    err_msg(e.as_str());
-                    return;
-                },
-                FailProgramCreation::ExistsAlready => {
-                    let job = job::Job::new(
-                        None,
-                        str::to_string(file),
-                        None,
-                        kern,
-                    );
-                    if let Ok(j) = job {
-                        kern.queue_job(j);
-                    } else {
-                        err_msg(job.unwrap_err());
-                        return
-                    }
+) -> Status {
+    let filenames = match args.split_last() {
+        Some((last, rest)) if !rest.is_empty() => match parse_mode(last) {
+            Some(mode) => {
+                if kern.mode != mode {
+                    kern.time_slice = mode.default_time_slice();
                 }
+                kern.mode = mode;
+                rest
+            }
+            None => args,
+        },
+        _ => args,
+    };
+    if filenames.is_empty() {
+        return bad_cmd(&kern.out, "usage: exec <FILENAME 1> <FILENAME 2> <etc...> [MODE] [&]")
+    }
+    
+    // Every program is loaded before any of them is queued, so a failure leaves none of them behind
+    let mut jobs = vec![];
+    for name in filenames {
+        match load_job(name, kern) {
+            Ok(job) => jobs.push(job),
+            Err(e) => {
+                discard_jobs(jobs, kern);
+                return err_msg(&kern.out, e.as_str())
             }
         }
     }
+    let pids: Vec<isize> = jobs.iter().map(|job| job.pid).collect();
+    for job in jobs {
+        kern.queue_job(job);
+    }
     // kern.memory_dump();
-    let res = kern.execute_schedule();
+    if background {
+        for pid in pids.iter() {
+            let filename = kern.procs.get(*pid).map(|pcb| pcb.filename.clone()).unwrap_or_default();
+            outln!(kern.out, "[{pid}] {filename}");
+        }
+        kern.background.extend(pids);
+        return SUCCESS
+    }
+    
+    // A job that runs exec waits for the programs it started
+    let parent = kern.running.as_ref().map(|job| job.pid);
+    if let Some(pid) = parent {
+        kern.procs.set_state(pid, ProcState::Blocked);
+    }
+    let res = kern.execute_until(&pids).map_err(String::from);
+    if let Some(pid) = parent {
+        kern.procs.set_state(pid, ProcState::Running);
+    }
     if let Err(r) = res {
-        err_msg(r);
-        return
+        return err_msg(&kern.out, r.as_str())
+    }
+    
+    // The programs are all done, so their exit statuses are collected and they leave the process table
+    let statuses: Vec<Option<Status>> = pids.iter().map(|pid| kern.procs.reap(*pid)).collect();
+    statuses.last().copied().flatten().unwrap_or(SUCCESS)
+}
+
+fn cat(filename: &str, fs: &mut FileSystem, out: &Output) -> Status {
+    match fs.read_file(filename) {
+        Ok(data) => {
+            for line in String::from_utf8_lossy(&data).lines() {
+                outln!(out, "{}", line);
+            }
+            SUCCESS
+        }
+        Err(e) => err_msg(out, format!("failed to open {}", e).as_str())
+    }
+}
+
+/// Copies a file from the host into the file system
+fn import(host_path: &str, path: &str, fs: &mut FileSystem, out: &Output) -> Status {
+    let data = match std::fs::read(host_path) {
+        Ok(d) => d,
+        Err(e) => return err_msg(out, format!("failed to read host file {}: {}", host_path, e).as_str())
+    };
+    
+    let mut dest = String::from(path);
+    if fs.is_dir(path) {
+        let name = Path::new(host_path).file_name().map(|n| n.to_string_lossy().into_owned());
+        dest = format!("{}/{}", path.trim_end_matches('/'), name.unwrap_or_default());
+    }
+    match fs.write_file(&dest, &data) {
+        Ok(()) => SUCCESS,
+        Err(e) => err_msg(out, e.to_string().as_str())
+    }
+}
+
+/// Copies a file from the file system out to the host
+fn host_export(path: &str, host_path: &str, fs: &mut FileSystem, out: &Output) -> Status {
+    let data = match fs.read_file(path) {
+        Ok(d) => d,
+        Err(e) => return err_msg(out, e.to_string().as_str())
+    };
+    
+    let mut dest = PathBuf::from(host_path);
+    if dest.is_dir() {
+        dest.push(path.rsplit('/').next().unwrap_or(path));
     }
+    match std::fs::write(&dest, data) {
+        Ok(()) => SUCCESS,
+        Err(e) => err_msg(out, format!("failed to write host file {}: {}", dest.display(), e).as_str())
+    }
+}
 
+/// Loads a program and makes a job of it, the job isn't queued yet
+fn load_job(name: &str, kern: &mut Kernel) -> Result<job::Job, String> {
+    // Programs are known by their absolute path, whatever directory they were started from
+    let file = kern.get_mut_fs()
+        .canonicalize(name)
+        .map_err(|e| format!("failed to open {}", e))?;
+    let program = job::Program::new(kern, &file)?;
+    job::Job::new(program, kern).map_err(String::from)
 }
 
-fn cat(filename: &str) {
-    let file = File::open(filename);
-    if let Ok(f) = file {
-        let reader = BufReader::new(f);
-        for (idx, line) in reader.lines().enumerate() {
+/// Frees jobs that were never queued and takes them out of the process table
+fn discard_jobs(jobs: Vec<job::Job>, kern: &mut Kernel) {
+    for job in jobs {
+        kern.procs.terminate(job.pid, FAILURE);
+        kern.procs.reap(job.pid);
+        if let Err(e) = kern.dealloc_program(job) {
+            err_msg(&kern.out, format!("failed to free the program's memory, {e}").as_str());
+        }
+    }
+}
 
-            match line {
-                Ok(ln) => println!("{}", ln),
-                Err(e) => {
-                    err_msg(
-                        format!("failed to read line {} from {} due to {}", idx, filename, e).
-                            as_str()
-                    );
-                    return
-                }
+fn parse_mode(mode: &str) -> Option<Mode> {
+    match mode {
+        "FCFS" => Some(Mode::FCFS),
+        "SJF" => Some(Mode::SJF),
+        "RR" => Some(Mode::RR),
+        "AGING" => Some(Mode::AGING),
+        _ => None
+    }
+}
+
+/// Lists the background jobs, the ones that are done are reported once and leave the process table
+fn jobs(kernel: &mut Kernel) -> Status {
+    for pid in kernel.background.clone() {
+        let Some(pcb) = kernel.procs.get(pid) else {
+            continue
+        };
+        let filename = pcb.filename.clone();
+        match pcb.status {
+            Some(status) => {
+                kernel.reap_background(pid);
+                outln!(kernel.out, "[{pid}] {:<16}{filename}", format!("DONE ({status})"));
             }
+            None => outln!(kernel.out, "[{pid}] {:<16}{filename}", pcb.state),
         }
-    } else {
-        err_msg(format!("failed to open {}", filename).as_str());
     }
+    SUCCESS
+}
+
+fn background_pids(input: &[String], kernel: &Kernel) -> Result<Vec<isize>, String> {
+    input
+        .iter()
+        .map(|pid| match pid.parse::<isize>() {
+            Ok(pid) if kernel.background.contains(&pid) => Ok(pid),
+            Ok(pid) => Err(format!("no background job with pid {pid}")),
+            Err(_) => Err(format!("invalid pid: {pid}")),
+        })
+        .collect()
+}
+
+/// Waits for a background job, the last one started by default, and returns its exit status
+fn fg(input: &[String], kernel: &mut Kernel) -> Status {
+    let pid = match background_pids(input, kernel) {
+        Ok(pids) => pids.first().or(kernel.background.last()).copied(),
+        Err(e) => return err_msg(&kernel.out, e.as_str())
+    };
+    let Some(pid) = pid else {
+        return err_msg(&kernel.out, "no background jobs")
+    };
+    if let Some(pcb) = kernel.procs.get(pid) {
+        outln!(kernel.out, "{}", pcb.filename);
+    }
+    wait_for(&[pid], kernel)
+}
+
+/// Waits for background jobs, all of them by default, the status is the one of the last job given
+fn wait(input: &[String], kernel: &mut Kernel) -> Status {
+    match background_pids(input, kernel) {
+        Ok(pids) if pids.is_empty() => wait_for(&kernel.background.clone(), kernel),
+        Ok(pids) => wait_for(&pids, kernel),
+        Err(e) => err_msg(&kernel.out, e.as_str())
+    }
+}
+
+fn wait_for(pids: &[isize], kernel: &mut Kernel) -> Status {
+    if let Err(e) = kernel.execute_until(pids).map_err(String::from) {
+        return err_msg(&kernel.out, e.as_str())
+    }
+    let statuses: Vec<Option<Status>> = pids.iter().map(|pid| kernel.reap_background(*pid)).collect();
+    statuses.last().copied().flatten().unwrap_or(SUCCESS)
 }
 
-fn setmod(mode: &str, kernel: &mut Kernel) {
+fn setmod(input: &[String], kernel: &mut Kernel) -> Status {
+    let mode = input[0].as_str();
     match mode {
         "FCFS" => { 
             kernel.mode = Mode::FCFS;
-            println!("Scheduler running in FCFS")
+            outln!(kernel.out, "Scheduler running in FCFS")
         }
         "SJF" => {
             kernel.mode = Mode::SJF;
-            println!("Scheduler running in SJF")
+            outln!(kernel.out, "Scheduler running in SJF")
         }
-        "RR" => {
-            kernel.mode = Mode::RR;
-            println!("Scheduler running in RR")
+        "RR" | "AGING" => {
+            let mode = if mode == "RR" { Mode::RR } else { Mode::AGING };
+            let slice = match input.get(1) {
+                Some(s) => match s.parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return err_msg(&kernel.out, format!("invalid time slice: {s}").as_str())
+                },
+                None => mode.default_time_slice(),
+            };
+            kernel.mode = mode;
+            kernel.time_slice = slice;
+            outln!(kernel.out, "Scheduler running in {} with a time slice of {slice}", input[0])
         }
-        _ => err_msg(format!("unknown scheduler mode: {mode}").as_str())
+        _ => return err_msg(&kernel.out, format!("unknown scheduler mode: {mode}").as_str())
     }
+    SUCCESS
 }
 
-fn ls() {
-    println!("Unimplemented")
+fn setpolicy(policy: &str, kernel: &mut Kernel) -> Status {
+    if policy == "OPT" {
+        return err_msg(&kernel.out, "OPT needs to know future references, compare it offline with pagestat")
+    }
+    match new_policy(policy, NUM_FRAMES, &[]) {
+        Some(p) => {
+            kernel.get_mut_ft().set_policy(p);
+            outln!(kernel.out, "Page replacement policy set to {policy}");
+            SUCCESS
+        }
+        None => err_msg(&kernel.out, format!("unknown page replacement policy: {policy}").as_str())
+    }
 }
 
-fn cd() {
-    println!("Unimplemented")
+fn pagestat(kernel: &mut Kernel) -> Status {
+    let ft = &kernel.frame_table;
+    // The two counts aren't comparable, the replay has no pages loaded up front and covers only the window
+    outln!(
+        kernel.out,
+        "{} page faults under {} since it was set, not counting the pages programs are loaded with",
+        ft.faults,
+        ft.policy.name()
+    );
+    
+    // Only a window of the latest references is kept, which keeps OPT's replay cheap
+    let refs: Vec<usize> = ft.references.iter().copied().collect();
+    outln!(
+        kernel.out,
+        "Replaying the last {} references (at most {} are kept) to {} pages over {} empty frames:",
+        refs.len(),
+        REFERENCE_WINDOW,
+        ft.num_pages_referenced(),
+        NUM_FRAMES
+    );
+    for name in POLICIES {
+        if let Some(mut policy) = new_policy(name, NUM_FRAMES, &refs) {
+            let faults = simulate(policy.as_mut(), &refs, NUM_FRAMES);
+            outln!(kernel.out, "{:<6} {} page faults", name, faults);
+        }
+    }
+    SUCCESS
 }
 
-fn touch() {
-    println!("Unimplemented")
+/// Lists the process table, terminated jobs stay until their status is collected
+fn ps(kernel: &mut Kernel) -> Status {
+    outln!(
+        kernel.out,
+        "{:<5}{:<6}{:<12}{:<7}{:<7}{:<8}{:<8}PROGRAM",
+        "PID",
+        "PPID",
+        "STATE",
+        "START",
+        "INSTR",
+        "FAULTS",
+        "STATUS"
+    );
+    for pcb in kernel.procs.iter() {
+        let ppid = pcb.ppid.map_or(String::from("-"), |p| p.to_string());
+        let status = pcb.status.map_or(String::from("-"), |s| s.to_string());
+        outln!(
+            kernel.out,
+            "{:<5}{:<6}{:<12}{:<7}{:<7}{:<8}{:<8}{}",
+            pcb.pid,
+            ppid,
+            pcb.state,
+            pcb.start,
+            pcb.instructions,
+            pcb.faults,
+            status,
+            pcb.filename
+        );
+    }
+    SUCCESS
 }
 
+fn kill(pids: &[String], kernel: &mut Kernel) -> Status {
+    let mut status = SUCCESS;
+    for pid in pids {
+        let res = match pid.parse::<isize>() {
+            Ok(pid) => kernel.kill(pid),
+            Err(_) => Err(format!("invalid pid: {pid}")),
+        };
+        if let Err(e) = res {
+            status = err_msg(&kernel.out, e.as_str());
+        }
+    }
+    status
+}
 
+fn pagetable(pid: &str, kernel: &mut Kernel) -> Status {
+    let Ok(pid) = pid.parse::<isize>() else {
+        return err_msg(&kernel.out, format!("invalid pid: {pid}").as_str())
+    };
+    match kernel.find_program(pid) {
+        Some(program) => {
+            let program = program.borrow();
+            outln!(kernel.out, "Page tables of pid {} ({}):", pid, program.filename);
+            kernel.segment_dump(&program);
+            for segment in program.segments.iter() {
+                outln!(kernel.out, "{} segment:", segment.kind);
+                segment.page_table.dump(&kernel.out);
+            }
+            SUCCESS
+        }
+        None => err_msg(&kernel.out, format!("no process with pid {pid}").as_str())
+    }
+}
 
+fn setlevels(levels: &str, kernel: &mut Kernel) -> Status {
+    match levels.parse::<usize>() {
+        Ok(n) if (1..=MAX_PAGE_TABLE_LEVELS).contains(&n) => {
+            kernel.pt_levels = n;
+            outln!(kernel.out, "Programs loaded from now on use {n}-level page tables");
+            SUCCESS
+        }
+        _ => err_msg(&kernel.out, format!("page tables have 1 to {MAX_PAGE_TABLE_LEVELS} levels, got {levels}").as_str())
+    }
+}
 
+fn tlbstat(tlb: &mut Tlb, out: &Output) -> Status {
+    let switch = if tlb.tagged { "tagged with pids" } else { "flushed on context switch" };
+    outln!(
+        out,
+        "TLB: {} entries, {}-way set associative, {:?}, {}",
+        tlb.size(),
+        tlb.ways(),
+        tlb.policy,
+        switch
+    );
+    outln!(out, "{} flushes", tlb.flushes);
+    outln!(out, "{:<6}{:<8}{:<8}{:<10}PROGRAM", "PID", "HITS", "MISSES", "HIT RATE");
+    
+    let (mut hits, mut misses) = (0, 0);
+    for (pid, stats) in tlb.stats.iter() {
+        hits += stats.hits;
+        misses += stats.misses;
+        outln!(
+            out,
+            "{:<6}{:<8}{:<8}{:<10}{}",
+            pid,
+            stats.hits,
+            stats.misses,
+            hit_rate(stats.hits, stats.misses),
+            stats.program
+        );
+    }
+    outln!(out, "{:<6}{:<8}{:<8}{:<10}", "TOTAL", hits, misses, hit_rate(hits, misses));
+    SUCCESS
+}
+
+fn hit_rate(hits: usize, misses: usize) -> String {
+    if hits + misses == 0 {
+        return String::from("-")
+    }
+    format!("{:.1}%", 100.0 * hits as f64 / (hits + misses) as f64)
+}
+
+/// Replaces the TLB with a new one, statistics start over
+fn settlb(input: &[String], kernel: &mut Kernel) -> Status {
+    let (Ok(size), Ok(ways)) = (input[0].parse::<usize>(), input[1].parse::<usize>()) else {
+        return err_msg(&kernel.out, "TLB entries and ways must be numbers")
+    };
+    if ways == 0 || size == 0 || size % ways != 0 {
+        return err_msg(&kernel.out, format!("a TLB of {size} entries can't be split into sets of {ways} ways").as_str())
+    }
+    let policy = match input.get(2).map(String::as_str) {
+        Some("FIFO") => TlbPolicy::FIFO,
+        Some("LRU") | None => TlbPolicy::LRU,
+        Some(p) => return err_msg(&kernel.out, format!("unknown TLB replacement policy: {p}").as_str())
+    };
+    let tagged = match input.get(3).map(String::as_str) {
+        Some("TAGGED") => true,
+        Some("FLUSH") | None => false,
+        Some(t) => return err_msg(&kernel.out, format!("TLB entries are either FLUSH or TAGGED, got {t}").as_str())
+    };
+    // A job running settlb goes on with the new TLB, which has to know whose it is
+    let mut tlb = Tlb::new(size, ways, policy, tagged);
+    if let Some(job) = &kernel.running {
+        tlb.switch_to(job.pid, &job.filename);
+    }
+    kernel.tlb = tlb;
+    outln!(kernel.out, "TLB set to {size} entries, {ways}-way set associative, {policy:?}");
+    SUCCESS
+}
+
+/// The heap and stack belong to a program's segments, so the interactive shell has none
+fn running_job(cmd: &str, kernel: &Kernel) -> Option<Job> {
+    if kernel.running.is_none() {
+        err_msg(&kernel.out, format!("{cmd} needs a process with memory, run it from a script").as_str());
+    }
+    kernel.running.clone()
+}
+
+fn alloc(name: &str, lines: &str, kernel: &mut Kernel) -> Status {
+    let Some(job) = running_job("alloc", kernel) else {
+        return FAILURE
+    };
+    let Ok(n) = lines.parse::<usize>() else {
+        return err_msg(&kernel.out, format!("invalid number of lines: {lines}").as_str())
+    };
+    let res = job.heap.borrow_mut().alloc(name, n);
+    match res {
+        Ok(allocation) => {
+            // Fresh memory is cleared, so nothing is left over from a previous allocation
+            for offset in allocation.start..allocation.start + n {
+                let res = kernel.write_memory(&job, SegmentKind::Data, offset, String::new());
+                if let Err(e) = res {
+                    return err_msg(&kernel.out, e.to_string().as_str())
+                }
+            }
+            SUCCESS
+        }
+        Err(e) => err_msg(&kernel.out, e.as_str())
+    }
+}
+
+fn free(name: &str, kernel: &mut Kernel) -> Status {
+    let Some(job) = running_job("free", kernel) else {
+        return FAILURE
+    };
+    let res = job.heap.borrow_mut().free(name);
+    match res {
+        Ok(()) => SUCCESS,
+        Err(e) => err_msg(&kernel.out, e.as_str())
+    }
+}
+
+fn push(val: String, kernel: &mut Kernel) -> Status {
+    let Some(job) = running_job("push", kernel) else {
+        return FAILURE
+    };
+    // Writing past the stack segment's limit is caught by the segment itself
+    match kernel.write_memory(&job, SegmentKind::Stack, job.sp.get(), val) {
+        Ok(()) => {
+            job.sp.set(job.sp.get() + 1);
+            SUCCESS
+        }
+        Err(e) => err_msg(&kernel.out, format!("stack overflow, {e}").as_str())
+    }
+}
+
+/// Prints the top of the stack, removing it unless peeking
+fn pop(remove: bool, kernel: &mut Kernel) -> Status {
+    let cmd = if remove { "pop" } else { "peek" };
+    let Some(job) = running_job(cmd, kernel) else {
+        return FAILURE
+    };
+    if job.sp.get() == 0 {
+        return err_msg(&kernel.out, "stack is empty")
+    }
+    match kernel.read_memory(&job, SegmentKind::Stack, job.sp.get() - 1) {
+        Ok(val) => {
+            outln!(kernel.out, "{val}");
+            if remove {
+                job.sp.set(job.sp.get() - 1);
+            }
+            SUCCESS
+        }
+        Err(e) => err_msg(&kernel.out, e.to_string().as_str())
+    }
+}
+
+fn heapstat(kernel: &mut Kernel) -> Status {
+    let Some(job) = running_job("heapstat", kernel) else {
+        return FAILURE
+    };
+    let heap = job.heap.borrow();
+    outln!(kernel.out, "Heap of pid {}: {} lines, {} allocator", job.pid, heap.size, heap.allocator.name());
+    outln!(kernel.out, "{:<10}{:<7}{:<11}BLOCK", "NAME", "START", "REQUESTED");
+    for (name, a) in heap.allocations.iter() {
+        outln!(kernel.out, "{:<10}{:<7}{:<11}{}", name, a.start, a.requested, a.size);
+    }
+    
+    let blocks = heap.allocator.free_blocks();
+    let free: usize = blocks.iter().map(|(_, size)| size).sum();
+    let largest = blocks.iter().map(|(_, size)| *size).max().unwrap_or(0);
+    let listed: Vec<String> = blocks.iter().map(|(start, size)| format!("{start}+{size}")).collect();
+    outln!(kernel.out, "Free blocks: {}", listed.join(" "));
+    outln!(
+        kernel.out,
+        "{} of {} lines free in {} blocks, the largest is {} lines",
+        free,
+        heap.size,
+        blocks.len(),
+        largest
+    );
+    outln!(kernel.out, "External fragmentation: {:.1}%", 100.0 * heap.external_fragmentation());
+    outln!(kernel.out, "Internal fragmentation: {} lines", heap.internal_fragmentation());
+    outln!(kernel.out, "Stack: {} of {} lines used", job.sp.get(), STACK_SEGMENT_SIZE);
+    SUCCESS
+}
+
+/// Picks the allocator of new jobs, the current job switches too if it hasn't allocated anything yet
+fn setalloc(name: &str, kernel: &mut Kernel) -> Status {
+    let Some(heap) = Heap::new(name, DATA_SEGMENT_SIZE) else {
+        return err_msg(&kernel.out, format!("unknown allocator: {name}, pick one of {}", ALLOCATORS.join(", ")).as_str())
+    };
+    kernel.allocator = String::from(name);
+    if let Some(job) = &kernel.running {
+        let mut current = job.heap.borrow_mut();
+        if current.allocations.is_empty() {
+            *current = heap;
+        }
+    }
+    outln!(kernel.out, "Heap allocator set to {name}");
+    SUCCESS
+}
+
+fn ls(path: &str, fs: &mut FileSystem, out: &Output) -> Status {
+    match fs.ls(path) {
+        Ok(entries) => {
+            for (name, kind) in entries {
+                if kind == InodeKind::Directory {
+                    outln!(out, "{name}/");
+                } else {
+                    outln!(out, "{name}");
+                }
+            }
+            SUCCESS
+        }
+        Err(e) => err_msg(out, e.to_string().as_str())
+    }
+}
+
+fn fsck(repair: bool, fs: &mut FileSystem, out: &Output) -> Status {
+    match fs.fsck(repair) {
+        Ok(problems) => {
+            for problem in problems.iter() {
+                outln!(out, "fsck: {problem}");
+            }
+            if problems.is_empty() {
+                outln!(out, "fsck: file system is clean");
+            } else if repair {
+                outln!(out, "fsck: repaired {} problems", problems.len());
+            } else {
+                outln!(out, "fsck: found {} problems, run fsck -r to repair them", problems.len());
+                return FAILURE
+            }
+            SUCCESS
+        }
+        Err(e) => err_msg(out, e.to_string().as_str())
+    }
+}
+
+fn cd(path: &str, fs: &mut FileSystem, out: &Output) -> Status {
+    match fs.cd(path) {
+        Ok(()) => SUCCESS,
+        Err(e) => err_msg(out, e.to_string().as_str())
+    }
+}
+
+fn mkdir(paths: &[String], fs: &mut FileSystem, out: &Output) -> Status {
+    let mut status = SUCCESS;
+    for path in paths {
+        if let Err(e) = fs.mkdir(path) {
+            status = err_msg(out, e.to_string().as_str());
+        }
+    }
+    status
+}
+
+fn touch(paths: &[String], fs: &mut FileSystem, out: &Output) -> Status {
+    let mut status = SUCCESS;
+    for path in paths {
+        if let Err(e) = fs.touch(path) {
+            status = err_msg(out, e.to_string().as_str());
+        }
+    }
+    status
+}
+
+fn rm(paths: &[String], fs: &mut FileSystem, out: &Output) -> Status {
+    let mut status = SUCCESS;
+    for path in paths {
+        if let Err(e) = fs.rm(path) {
+            status = err_msg(out, e.to_string().as_str());
+        }
+    }
+    status
+}
+
+fn rmdir(paths: &[String], fs: &mut FileSystem, out: &Output) -> Status {
+    let mut status = SUCCESS;
+    for path in paths {
+        if let Err(e) = fs.rmdir(path) {
+            status = err_msg(out, e.to_string().as_str());
+        }
+    }
+    status
+}
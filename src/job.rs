@@ -1,26 +1,29 @@
 use std::{
-    fs::File,
-    io::{BufRead, BufReader},
+    cell::{Cell, RefCell},
+    collections::HashMap,
     rc::Rc,
-    sync::atomic::{AtomicIsize, Ordering},
 };
+use crate::control::{analyze, CallFrame, ControlTable, ForLoop};
+use crate::filesystem::FileSystem;
 use crate::kernel::{Kernel};
-use crate::shellmemory::{FRAME_SIZE};
+use crate::heap::Heap;
+use crate::interpreter::{Status, SUCCESS};
+use crate::shellmemory::VarMemory;
+use crate::errors::Exception;
+use crate::pagetable::{PageTableEntry, Protection};
+use crate::segment::{Segment, SegmentKind, DATA_SEGMENT_SIZE, STACK_SEGMENT_SIZE};
+use crate::shellmemory::{DEMAND_PAGE_LIMIT, FRAME_SIZE};
 
-#[derive(Debug, Clone)]
-pub(crate) enum FailProgramCreation{
-    ExistsAlready,
-    Error(String)
-}
-
-static GLOBAL_PID: AtomicIsize = AtomicIsize::new(0);
+pub(crate) const BACKING_STORE: &str = "/backing_store";
 
-fn assign_pid() -> isize {
-    GLOBAL_PID.fetch_add(1, Ordering::Relaxed)
+/// Translates an offset in a segment to its index in program memory, None if its page isn't present
+pub(crate) fn find_mem_idx(segment: &mut Segment, offset: usize) -> Result<Option<usize>, Exception> {
+    segment.linear(offset)?;
+    let frame_idx = segment.page_table.translate(offset / FRAME_SIZE);
+    Ok(frame_idx.map(|f| frame_mem_idx(f, offset % FRAME_SIZE)))
 }
 
-pub(crate) fn find_mem_idx(frame_idx: usize, pc: usize) -> usize {
-    let offset = pc % FRAME_SIZE;
+pub(crate) fn frame_mem_idx(frame_idx: usize, offset: usize) -> usize {
     FRAME_SIZE * frame_idx + offset
 }
 
@@ -30,46 +33,42 @@ pub(crate) struct Job {
     pub(crate) pc: usize,
     pub(crate) size: usize,
     pub(crate) filename: String,
-    pub(crate) program: Rc<Program>
+    pub(crate) program: Rc<RefCell<Program>>,
+    pub(crate) score: usize, // Job length score used by AGING, starts at the program size
+    pub(crate) vars: Rc<RefCell<VarMemory>>, // The job's own variables, shared with its running copy in the kernel
+    pub(crate) heap: Rc<RefCell<Heap>>,      // Allocations in the data segment
+    pub(crate) sp: Rc<Cell<usize>>,          // Offset of the next free line in the stack segment
+    pub(crate) status: Rc<Cell<Status>>,     // Status of the last command, the job's exit status once it is done
+    pub(crate) frames: Vec<CallFrame>,       // Functions being called, innermost last
+    pub(crate) loops: HashMap<usize, ForLoop>, // `for` loops being run, by the line of their header
 }
 
 impl Job {
-    pub(crate) fn new<'a>(
-        size: Option<usize>,
-        filename: String,
-        program: Option<Program>,
-        kern: &mut Kernel,
-    ) -> Result<Job, &'a str> {
-        
-        // This option is left for when a job already exists
-        if program.is_none() && size.is_none() {
-            for job in kern.job_queue.iter() {
-                if job.filename == filename {
-                    return Ok(
-                        Job{
-                            pid: assign_pid(),
-                            pc: 0,
-                            size: job.program.size,
-                            filename,
-                            program: Rc::clone(&job.program)
-                        }
-                    )
-                }
-            }
-            return Err("Illegal state, no job found")
-        }
-
-        if size.is_none() {
-            return Err("Illegal state, abort execution")
-        }
-        
+    /// Makes a job of a loaded program, the pid is only assigned once nothing can fail
+    pub(crate) fn new(program: Rc<RefCell<Program>>, kern: &mut Kernel) -> Result<Job, &'static str> {
+        let heap = Heap::new(&kern.allocator, DATA_SEGMENT_SIZE).ok_or("Unknown heap allocator")?;
+        let heap = Rc::new(RefCell::new(heap));
+        let (size, filename) = {
+            let program = program.borrow();
+            (program.size, program.filename.clone())
+        };
+        let pid = kern.assign_pid();
+        let ppid = kern.running.as_ref().map(|job| job.pid);
+        kern.procs.create(pid, ppid, &filename, kern.clock);
         Ok(
             Job{
-                pid: assign_pid(),
+                pid,
                 pc: 0,
-                size: size.unwrap(),
+                size,
                 filename,
-                program: Rc::new(program.unwrap()),
+                program,
+                score: size,
+                vars: Rc::new(RefCell::new(kern.inherit_vars())),
+                heap,
+                sp: Rc::new(Cell::new(0)),
+                status: Rc::new(Cell::new(SUCCESS)),
+                frames: vec![],
+                loops: HashMap::new(),
             }
         )
     }
@@ -78,84 +77,134 @@ impl Job {
 #[derive(Clone, Debug)]
 pub(crate) struct Program {
     pub(crate) filename: String,
+    pub(crate) backing_file: String, // Copy of the program in the backing store, pages come from here
     pub(crate) size: usize,
-    pub(crate) page_table: Vec<isize>,
+    pub(crate) segments: Vec<Segment>, // Code, data and stack, in that order
+    pub(crate) control: ControlTable,  // Blocks and functions of the code, matched at load time
 }
 
 impl Program {
     pub(crate) fn new(
         kern: &mut Kernel,
         filename: &str,
-    ) -> Result<Program, FailProgramCreation> {
+    ) -> Result<Rc<RefCell<Program>>, String> {
+        // Every job gets its own program, even of a file another job is running,
+        // since the data and stack segments are written to
+        let lines = read_lines(kern.get_mut_fs(), filename)
+            ?;
+        let size = lines.len();
+        let control = analyze(&lines)
+            .map_err(|e| format!("{filename}: {e}"))?;
+        let num_pages = size.div_ceil(FRAME_SIZE);
         
-        let mut size = 0;
-        let file = File::open(filename);
-        let mut lines: Vec<String> = vec![];
+        // Segments are laid out back to back, each starting on a page boundary
+        let levels = kern.pt_levels;
+        let code = Segment::new(SegmentKind::Code, 0, size, levels, Protection::ReadExecute);
+        let data_base = num_pages * FRAME_SIZE;
+        let data = Segment::new(SegmentKind::Data, data_base, DATA_SEGMENT_SIZE, levels, Protection::ReadWrite);
+        let stack_base = data_base + DATA_SEGMENT_SIZE.div_ceil(FRAME_SIZE) * FRAME_SIZE;
+        let stack = Segment::new(SegmentKind::Stack, stack_base, STACK_SEGMENT_SIZE, levels, Protection::ReadWrite);
+        let segments = vec![code, data, stack]
+            .into_iter()
+            .collect::<Result<Vec<Segment>, String>>()
+            .map_err(|e| format!("{filename}: {e}"))?;
         
-        for job in kern.job_queue.iter() {
-            if job.filename == filename {
-                return Err(FailProgramCreation::ExistsAlready)
-            }
-        }
+        // The program runs off its own copy, so changes to the original don't affect it.
+        // The copy covers the whole address space so that data and stack pages can be swapped out too.
+        let mut image = lines;
+        image.resize(segments[2].end(), String::new());
+        let backing_file = kern.assign_backing_file()
+            .map_err(|e| format!("failed to set up the backing store: {}", e))?;
+        write_lines(kern.get_mut_fs(), &backing_file, &image)
+            ?;
         
-        if let Ok(f) = file {
-            let reader = BufReader::new(f);
-            for (idx, line) in reader.lines().enumerate() {
-                match line {
-                    Ok(ln) => { lines.push(ln); size += 1; }
-                    Err(e) => {
-                        return Err(
-                            FailProgramCreation::Error(
-                                format!("failed to read line {} from {} due to {}", idx, filename, e)
-                            )
-                        )
-                    } 
-                }
+        let program = Rc::new(RefCell::new(
+            Program{
+                filename: String::from(filename),
+                backing_file,
+                size,
+                segments,
+                control,
             }
-        } else {
-            return Err(
-                FailProgramCreation::Error(
-                    format!("failed to open {}", filename)
-                )
-            )
-        }
-        
-        let num_frames = (size + FRAME_SIZE - 1) / FRAME_SIZE;
-        let ft = kern.get_mut_ft();
-        let page_table = ft.alloc_frames(num_frames, String::from(filename));
+        ));
         
-        let mut line_count = 0;
-        let p_mem = kern.get_mut_pmem();
-        
-        for frame_idx in &page_table {
-            let mut frame_count = 0;
-            
-            // Allocate at most 4 frames FOR NOW
-            while line_count < size && frame_count < num_frames {
-                for _ in 0..FRAME_SIZE {
-                    
-                    if line_count == size { break }
-                    
-                    let ln = lines.get(line_count);
-                    
-                    if let Some(l) = ln {
-                        p_mem.write_to_frame(*frame_idx as usize, line_count, l.clone());
-                        line_count += 1;
-                    } else {
-                        break
-                    }
-                }
-                frame_count += 1;
-            } 
+        // Only the first few pages are resident at load time, the rest are faulted in
+        for page in 0..num_pages.min(DEMAND_PAGE_LIMIT) {
+            kern.load_page(&program, page)
+                .map_err(|e| e.to_string())?;
         }
         
-        Ok(
-            Program{
-                filename: filename.parse().unwrap(),
-                size,
-                page_table
-            }
-        )
+        Ok(program)
+    }
+    
+    pub(crate) fn segment(&self, kind: SegmentKind) -> &Segment {
+        &self.segments[kind as usize]
+    }
+    
+    pub(crate) fn segment_mut(&mut self, kind: SegmentKind) -> &mut Segment {
+        &mut self.segments[kind as usize]
+    }
+    
+    /// The segment holding a page of the linear address space
+    pub(crate) fn segment_of(&self, page: usize) -> Option<&Segment> {
+        self.segments.iter().find(|s| s.contains_page(page))
+    }
+    
+    /// The page table entry of a page of the linear address space
+    pub(crate) fn page_entry(&mut self, page: usize) -> Option<&mut PageTableEntry> {
+        let segment = self.segments.iter_mut().find(|s| s.contains_page(page))?;
+        let first_page = segment.first_page();
+        Some(segment.page_table.get_mut(page - first_page))
+    }
+    
+    /// Every valid page of the linear address space with its entry
+    pub(crate) fn pages(&self) -> Vec<(usize, PageTableEntry)> {
+        self.segments
+            .iter()
+            .flat_map(|s| {
+                s.page_table
+                    .pages()
+                    .into_iter()
+                    .map(|(page, entry)| (s.first_page() + page, entry))
+            })
+            .collect()
     }
 }
 
+fn read_lines(fs: &mut FileSystem, filename: &str) -> Result<Vec<String>, String> {
+    let data = fs.read_file(filename)
+        .map_err(|e| format!("failed to open {}", e))?;
+    
+    String::from_utf8(data)
+        .map(|text| text.lines().map(str::to_string).collect())
+        .map_err(|_| format!("failed to read {} as text", filename))
+}
+
+fn write_lines(fs: &mut FileSystem, filename: &str, lines: &[String]) -> Result<(), String> {
+    let mut text = lines.join("\n");
+    text.push('\n');
+    fs.write_file(filename, text.as_bytes())
+        .map_err(|e| format!("failed to write to the backing store: {}", e))
+}
+
+/// Reads the lines making up `page` from the program's backing file.
+pub(crate) fn read_page(fs: &mut FileSystem, filename: &str, page: usize) -> Result<Vec<String>, String> {
+    let lines = read_lines(fs, filename)?;
+    Ok(lines.into_iter().skip(page * FRAME_SIZE).take(FRAME_SIZE).collect())
+}
+
+/// Writes a page back to the program's backing file, lines past the end of the program are dropped.
+pub(crate) fn write_page(
+    fs: &mut FileSystem,
+    filename: &str,
+    page: usize,
+    page_lines: &[String],
+) -> Result<(), String> {
+    let mut lines = read_lines(fs, filename)?;
+    for (offset, line) in page_lines.iter().enumerate() {
+        if let Some(l) = lines.get_mut(page * FRAME_SIZE + offset) {
+            *l = line.clone();
+        }
+    }
+    write_lines(fs, filename, &lines)
+}
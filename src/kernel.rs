@@ -1,60 +1,163 @@
 use std::{
+    cell::RefCell,
     cmp::PartialEq,
     collections::VecDeque,
-    mem::drop,
     rc::Rc,
 };
-use crate::interpreter::interpreter;
-use crate::job::{Job, find_mem_idx};
-use crate::shellmemory::{Frame, FrameTable, ProgMemory, VarMemory, FRAME_SIZE};
+use crate::control;
+use crate::errors::Exception;
+use crate::filesystem::{FileSystem, FsError, InodeKind};
+use crate::interpreter::{err_msg, Status, FAILURE, SUCCESS};
+use crate::job::{Job, Program, BACKING_STORE, find_mem_idx, frame_mem_idx, read_page, write_page};
+use crate::heap::DEFAULT_ALLOCATOR;
+use crate::output::{outln, Output};
+use crate::pagetable::{Protection, PAGE_TABLE_LEVELS};
+use crate::shellmemory::{FrameTable, ProgMemory, VarMemory, FRAME_SIZE};
+use crate::proctable::{ProcState, ProcessTable, KILLED};
+use crate::segment::{SegmentKind, SEGMENTS};
+use crate::tlb::Tlb;
 
+pub(crate) const RR_TIME_SLICE: usize = 2;
+pub(crate) const AGING_TIME_SLICE: usize = 1;
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq)]
 pub(crate) enum Mode {
     FCFS,
     RR,
     SJF,
+    AGING,
+}
+
+impl Mode {
+    /// The time slice a mode starts with, only RR and AGING preempt jobs
+    pub(crate) fn default_time_slice(&self) -> usize {
+        match self {
+            Mode::AGING => AGING_TIME_SLICE,
+            _ => RR_TIME_SLICE,
+        }
+    }
 }
 
-pub(crate) struct Kernel<'a> {
+pub(crate) struct Kernel {
     pub(crate) job_queue: VecDeque<Job>, // A Job should not outlive the Kernel
-    pub(crate) lru_cache: VecDeque<&'a Frame>,
     pub(crate) prog_memory: ProgMemory,
-    pub(crate) var_memory: VarMemory,
+    pub(crate) var_memory: Rc<RefCell<VarMemory>>, // The interactive shell's variables
     pub(crate) frame_table: FrameTable,
-    pub(crate) mode: Mode
+    pub(crate) tlb: Tlb,
+    pub(crate) fs: FileSystem,
+    pub(crate) mode: Mode,
+    pub(crate) time_slice: usize,
+    pub(crate) pt_levels: usize, // Page table depth of programs loaded from now on
+    pub(crate) running: Option<Job>,
+    pub(crate) allocator: String, // Heap allocator of jobs created from now on
+    pub(crate) status: Status, // The interactive shell's last status
+    pub(crate) exit_requested: Option<Status>, // Set by `exit`, the current process ends after its command
+    pub(crate) out: Output, // Everything the shell and its programs print goes here
+    pub(crate) procs: ProcessTable,
+    pub(crate) clock: usize, // Instructions run since boot, the time the process table is kept in
+    pub(crate) background: Vec<isize>, // Jobs started with `exec ... &` that weren't waited for yet
+    next_pid: isize,
+    next_backing_id: usize,
 }
 
-impl<'a> Kernel<'a> {
+impl Kernel {
     
     pub(crate) fn new(
         mode: Mode,
         prog_memory: ProgMemory,
         var_memory: VarMemory,
         frame_table: FrameTable,
-    ) -> Kernel<'a> {
+        tlb: Tlb,
+        fs: FileSystem,
+        out: Output,
+    ) -> Kernel {
         Kernel{
             job_queue: VecDeque::new(),
-            lru_cache: VecDeque::new(),
             prog_memory,
-            var_memory,
+            var_memory: Rc::new(RefCell::new(var_memory)),
             frame_table,
-            mode
+            tlb,
+            fs,
+            mode,
+            time_slice: RR_TIME_SLICE,
+            pt_levels: PAGE_TABLE_LEVELS,
+            running: None,
+            allocator: String::from(DEFAULT_ALLOCATOR),
+            status: SUCCESS,
+            exit_requested: None,
+            out,
+            procs: ProcessTable::default(),
+            clock: 0,
+            background: vec![],
+            next_pid: 0,
+            next_backing_id: 0,
         }
     }
     
-    pub(crate) fn get_mut_pmem(&mut self) -> &mut ProgMemory {
-        &mut self.prog_memory
+    /// Variables of the current process, the running job or the shell when no job is running
+    pub(crate) fn get_varmem(&self) -> Rc<RefCell<VarMemory>> {
+        match &self.running {
+            Some(job) => Rc::clone(&job.vars),
+            None => Rc::clone(&self.var_memory),
+        }
     }
     
-    pub(crate) fn get_mut_varmem(&mut self) -> &mut VarMemory {
-        &mut self.var_memory
+    /// Status of the last command of the current process, what `$?` expands to
+    pub(crate) fn last_status(&self) -> Status {
+        match &self.running {
+            Some(job) => job.status.get(),
+            None => self.status,
+        }
+    }
+    
+    pub(crate) fn set_status(&mut self, status: Status) {
+        match &self.running {
+            Some(job) => job.status.set(status),
+            None => self.status = status,
+        }
+    }
+    
+    /// Variables a process started now would begin with, the ones exported by the current process
+    pub(crate) fn inherit_vars(&self) -> VarMemory {
+        self.get_varmem().borrow().inherit()
     }
     
     pub(crate) fn get_mut_ft(&mut self) -> &mut FrameTable {
         &mut self.frame_table
     }
     
+    pub(crate) fn get_mut_tlb(&mut self) -> &mut Tlb {
+        &mut self.tlb
+    }
+    
+    pub(crate) fn get_mut_fs(&mut self) -> &mut FileSystem {
+        &mut self.fs
+    }
+    
+    pub(crate) fn assign_pid(&mut self) -> isize {
+        self.next_pid += 1;
+        self.next_pid - 1
+    }
+    
+    /// A file of the backing store no other program uses
+    pub(crate) fn assign_backing_file(&mut self) -> Result<String, FsError> {
+        self.ensure_backing_store()?;
+        self.next_backing_id += 1;
+        Ok(format!("{}/{}", BACKING_STORE, self.next_backing_id - 1))
+    }
+    
+    /// Finds the program of a job by pid, whether it is running or waiting in the queue
+    pub(crate) fn find_program(&self, pid: isize) -> Option<Rc<RefCell<Program>>> {
+        self.running
+            .iter()
+            .chain(self.job_queue.iter())
+            .find(|job| job.pid == pid)
+            .map(|job| Rc::clone(&job.program))
+    }
+    
     pub(crate) fn queue_job(&mut self, job: Job) {
+        self.procs.set_state(job.pid, ProcState::Ready);
         match self.mode {
             Mode::FCFS => {
                 self.job_queue.push_back(job)
@@ -65,14 +168,27 @@ impl<'a> Kernel<'a> {
             Mode::SJF => {
                 self.queue_sjf(job)
             }
+            Mode::AGING => {
+                self.queue_aging(job)
+            }
         }
     }
     
+    pub(crate) fn queue_aging(&mut self, job: Job) {
+        for (i, j) in self.job_queue.iter().enumerate() {
+            if j.score > job.score {
+                self.job_queue.insert(i, job);
+                return
+            }
+        }
+        self.job_queue.push_back(job)
+    }
+    
     pub(crate) fn queue_sjf(&mut self, job: Job) {
-        let len = job.program.size;
+        let len = job.program.borrow().size;
         
         for (i, j) in self.job_queue.iter().enumerate() {
-            if j.program.size > len {
+            if j.program.borrow().size > len {
                 self.job_queue.insert(i, job);
                 return
             }
@@ -80,110 +196,444 @@ impl<'a> Kernel<'a> {
         self.job_queue.push_back(job)
     }
     
-    pub(crate) fn dealloc_program(&mut self, job: Job) -> Result<usize, &str> {
+    /// Frees the frames and backing file of a finished job's program. Every job loads its own
+    /// program, so it is freed even while copies of the job, e.g. in `running`, still hold it.
+    /// Its pages are marked not present so that those copies can't reach the freed frames.
+    pub(crate) fn dealloc_program(&mut self, job: Job) -> Result<(), Exception> {
         let program = job.program;
-        let rc = Rc::strong_count(&program);
-
-        // rc == 1 means that we should be the last the program holding a ref to the program
-        if rc == 1 {
-            dbg!("dropping program");
-            let pt = program.page_table.clone();
-            for page in pt.iter() {
-                let frame = self.frame_table.frames.get_mut(*page as usize);
-                if let Some(f) = frame {
-                    f.set_invalid();
-                } else {
-                    panic!("Failed to find frame while deallocating memory")
-                }
+        let pages = program.borrow().pages();
+        for (page, entry) in pages.iter().filter(|(_, e)| e.present) {
+            if self.frame_table.frames.get(entry.frame).is_none() {
+                return Err(Exception::IllegalKernelState(
+                    format!("page {page} is present in frame {} which doesn't exist", entry.frame)
+                ))
+            }
+            self.frame_table.free(entry.frame);
+            self.tlb.invalidate_frame(entry.frame);
+            if let Some(entry) = program.borrow_mut().page_entry(*page) {
+                entry.present = false;
             }
         }
-
-        // drop a reference to the program or drop entirely if we are the last user
-        drop(program);
-        Ok(rc - 1)
+        let backing_file = program.borrow().backing_file.clone();
+        self.frame_table.forget_program(&backing_file);
+        if let Err(e) = self.fs.rm(&backing_file) {
+            err_msg(&self.out, format!("failed to clean up the backing store: {}", e).as_str());
+        }
+        Ok(())
     }
     
-    pub(crate) fn execute_schedule(
+    /// Brings `page` of a program into memory, evicting another page if no frame is free
+    pub(crate) fn load_page(
         &mut self,
-    ) -> Result<(), &str> {
-
-        if self.job_queue.len() == 0 {
-            return Err("No job to execute")
+        program: &Rc<RefCell<Program>>,
+        page: usize,
+    ) -> Result<(), Exception> {
+        let backing_file = program.borrow().backing_file.clone();
+        let lines = read_page(&mut self.fs, &backing_file, page).map_err(Exception::PageFault)?;
+        
+        let frame_idx = match self.frame_table.find_free_frame() {
+            Some(idx) => idx,
+            None => self.evict_victim()?,
+        };
+        for offset in 0..FRAME_SIZE {
+            let line = lines.get(offset).cloned().unwrap_or_default();
+            self.prog_memory.write_to_frame(frame_idx, offset, line)?;
         }
         
-        let res: Result<(), &str>;
-        match self.mode {
-            Mode::FCFS => res = self.execute_fcfs(),
-            Mode::SJF => res = self.execute_fcfs(),
-            Mode::RR => res = self.execute_rr(0),
+        // Programs are told apart by their backing file, jobs of the same file each have their own
+        self.frame_table.assign(frame_idx, backing_file, page, Rc::downgrade(program));
+        let mut program = program.borrow_mut();
+        let entry = program
+            .page_entry(page)
+            .ok_or(Exception::PageFault(format!("page {page} is outside of the address space")))?;
+        entry.frame = frame_idx;
+        entry.present = true;
+        entry.dirty = false;
+        entry.referenced = false;
+        Ok(())
+    }
+    
+    /// Evicts the frame picked by the replacement policy and returns its index, now free for reuse
+    fn evict_victim(&mut self) -> Result<usize, Exception> {
+        let victim = self.frame_table.select_victim()?;
+        
+        outln!(self.out, "Page fault! Victim page contents:\n");
+        for offset in 0..FRAME_SIZE {
+            let line = self.prog_memory.read(frame_mem_idx(victim, offset))?;
+            if !line.is_empty() {
+                outln!(self.out, "{line}");
+            }
+        }
+        outln!(self.out, "\nEnd of victim page contents.");
+        
+        self.write_back(victim)?;
+        self.frame_table.evict(victim);
+        self.tlb.invalidate_frame(victim);
+        Ok(victim)
+    }
+    
+    /// Saves a frame to its owner's backing file if the page was modified since it was loaded
+    fn write_back(&mut self, frame_idx: usize) -> Result<(), Exception> {
+        let frame = &self.frame_table.frames[frame_idx];
+        let Some(owner) = frame.owner.upgrade() else {
+            return Ok(())
+        };
+        let page = frame.page;
+        let (dirty, backing_file) = {
+            let mut program = owner.borrow_mut();
+            (program.page_entry(page).is_some_and(|e| e.dirty), program.backing_file.clone())
+        };
+        if !dirty {
+            return Ok(())
+        }
+        
+        let lines = (0..FRAME_SIZE)
+            .map(|offset| self.prog_memory.read(frame_mem_idx(frame_idx, offset)))
+            .collect::<Result<Vec<String>, Exception>>()?;
+        if let Err(e) = write_page(&mut self.fs, &backing_file, page, &lines) {
+            err_msg(&self.out, e.as_str());
         }
-        res
+        Ok(())
     }
     
-    fn execute_fcfs(&mut self) -> Result<(), &str> {
-        while self.job_queue.len() > 0 {
-            let job = self.job_queue.pop_front();
-            if let Some(j) = job {
-                self.execute_whole_program(j);
-            } else {
-                return Err("Error fetching job")
+    /// Creates the backing store, emptying out anything a previous run left behind
+    pub(crate) fn init_backing_store(&mut self) -> Result<(), FsError> {
+        self.ensure_backing_store()?;
+        for (name, kind) in self.fs.ls(BACKING_STORE)? {
+            if kind == InodeKind::File {
+                self.fs.rm(format!("{}/{}", BACKING_STORE, name).as_str())?;
             }
         }
         Ok(())
     }
     
-    fn execute_rr(&mut self, round: usize) -> Result<(), &str> {
-        let mut rnd = round;
-        if round == 0 {
-            rnd = 2;
+    /// Creates the backing store if it was removed, it is an ordinary directory users can delete.
+    /// A file that took its place is removed first.
+    fn ensure_backing_store(&mut self) -> Result<(), FsError> {
+        if self.fs.is_dir(BACKING_STORE) {
+            return Ok(())
+        }
+        match self.fs.rm(BACKING_STORE) {
+            Ok(()) | Err(FsError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+        self.fs.mkdir(BACKING_STORE).map(|_| ())
+    }
+    
+    /// Runs jobs until all of `pids` have terminated. Other jobs in the queue get their turns too,
+    /// so background jobs keep going while the shell or a program waits.
+    pub(crate) fn execute_until(&mut self, pids: &[isize]) -> Result<(), &str> {
+        let done = |kernel: &Kernel, pid: &isize| {
+            kernel.procs.get(*pid).is_none_or(|pcb| pcb.state == ProcState::Terminated)
+        };
+        while !pids.iter().all(|pid| done(self, pid)) {
+            self.schedule_step()?;
+        }
+        Ok(())
+    }
+    
+    /// The interactive shell counts as a job too, it gives up the CPU after each command.
+    /// Under RR and AGING every queued job then gets a turn before the shell's next command,
+    /// under FCFS and SJF the shell keeps the CPU and background jobs only run when it waits for them.
+    pub(crate) fn shell_yield(&mut self) {
+        if !matches!(self.mode, Mode::RR | Mode::AGING) {
+            return
+        }
+        for _ in 0..self.job_queue.len() {
+            if self.schedule_step().is_err() {
+                break
+            }
+        }
+    }
+    
+    /// Gives the job at the front of the queue its turn under the current mode
+    fn schedule_step(&mut self) -> Result<(), &'static str> {
+        let Some(job) = self.job_queue.pop_front() else {
+            return Err("No job to execute")
+        };
+        match self.mode {
+            Mode::FCFS => self.execute_whole_program(job),
+            Mode::SJF => self.execute_whole_program(job),
+            Mode::RR => self.step_rr(job),
+            Mode::AGING => self.step_aging(job),
         }
-        // TODO
         Ok(())
     }
     
-    fn execute_rr_program(&mut self, mut job: Job) {
+    fn step_rr(&mut self, job: Job) {
+        // An unfinished job goes to the back of the queue once its slice is used up
+        if let Some(j) = self.execute_rr_program(job, self.time_slice) {
+            self.job_queue.push_back(j);
+        }
+    }
+    
+    fn step_aging(&mut self, job: Job) {
+        let unfinished = self.execute_rr_program(job, self.time_slice);
+        
+        // Waiting jobs age every time slice, the queue stays sorted since they all age equally
+        for waiting in self.job_queue.iter_mut() {
+            waiting.score = waiting.score.saturating_sub(1);
+        }
+        let Some(j) = unfinished else {
+            return
+        };
         
+        // Preempt the running job as soon as a waiting one becomes shorter
+        let preempt = self.job_queue
+            .front()
+            .is_some_and(|waiting| waiting.score < j.score);
+        if preempt {
+            self.queue_aging(j);
+        } else {
+            self.job_queue.push_front(j);
+        }
+    }
+    
+    fn execute_rr_program(&mut self, mut job: Job, round: usize) -> Option<Job> {
+        for _ in 0..round {
+            if job.pc == job.size {
+                break
+            }
+            self.execute_instruction(&mut job);
+        }
+        
+        if job.pc == job.size {
+            self.finish(job);
+            return None
+        }
+        Some(job)
     }
     
     fn execute_whole_program(&mut self, mut job: Job) {
+        while job.pc < job.size {
+            self.execute_instruction(&mut job);
+        }
+        self.finish(job);
+    }
+    
+    /// Frees the memory of a job that is done, it stays in the process table until reaped.
+    /// A failure is reported but the shell keeps going.
+    fn finish(&mut self, job: Job) {
+        if self.procs.get(job.pid).is_some_and(|pcb| pcb.killed) {
+            job.status.set(KILLED);
+        }
+        self.procs.terminate(job.pid, job.status.get());
+        if let Err(e) = self.dealloc_program(job) {
+            err_msg(&self.out, format!("failed to free the program's memory, {e}").as_str());
+        }
+    }
+    
+    /// Translates an offset in a segment to a program memory index, the TLB is checked before
+    /// the page table. The TLB caches pages of the linear address space. None means the page isn't resident.
+    fn translate(&mut self, job: &Job, kind: SegmentKind, offset: usize) -> Result<Option<usize>, Exception> {
+        let page = job.program.borrow().segment(kind).linear(offset)? / FRAME_SIZE;
+        if let Some(frame_idx) = self.tlb.lookup(job.pid, page) {
+            // The page table still learns about the access, replacement relies on it
+            job.program.borrow_mut().segment_mut(kind).page_table.reference(offset / FRAME_SIZE);
+            return Ok(Some(frame_mem_idx(frame_idx, offset % FRAME_SIZE)))
+        }
+        self.walk_page_table(job, kind, offset)
+    }
+    
+    /// Translates an offset with the page table alone and caches the translation in the TLB
+    fn walk_page_table(&mut self, job: &Job, kind: SegmentKind, offset: usize) -> Result<Option<usize>, Exception> {
+        let page = job.program.borrow().segment(kind).linear(offset)? / FRAME_SIZE;
+        let mem_idx = find_mem_idx(job.program.borrow_mut().segment_mut(kind), offset)?;
+        if let Some(idx) = mem_idx {
+            self.tlb.insert(job.pid, page, idx / FRAME_SIZE);
+        }
+        Ok(mem_idx)
+    }
+    
+    /// Finds where an offset in a segment lives in program memory, faulting its page in if needed
+    pub(crate) fn access_memory(&mut self, job: &Job, kind: SegmentKind, offset: usize) -> Result<usize, Exception> {
+        let mem_idx = match self.translate(job, kind, offset)? {
+            Some(idx) => idx,
+            None => {
+                let page = job.program.borrow().segment(kind).linear(offset)? / FRAME_SIZE;
+                self.handle_page_fault(&job.program, page)?;
+                // The access is retried once the page is in, like a restarted instruction.
+                // The retry skips the TLB, the fault already counted as its miss.
+                self.walk_page_table(job, kind, offset)?.ok_or(Exception::IllegalKernelState(
+                    String::from("page was just loaded but has no frame")
+                ))?
+            }
+        };
+        self.frame_table.access(mem_idx / FRAME_SIZE);
+        Ok(mem_idx)
+    }
+    
+    pub(crate) fn read_memory(&mut self, job: &Job, kind: SegmentKind, offset: usize) -> Result<String, Exception> {
+        let mem_idx = self.access_memory(job, kind, offset)?;
+        self.prog_memory.read(mem_idx)
+    }
+    
+    /// Writes a line of a segment, the page is marked dirty so it is saved when evicted
+    pub(crate) fn write_memory(
+        &mut self,
+        job: &Job,
+        kind: SegmentKind,
+        offset: usize,
+        val: String,
+    ) -> Result<(), Exception> {
+        let mem_idx = self.access_memory(job, kind, offset)?;
+        {
+            let mut program = job.program.borrow_mut();
+            let segment = program.segment_mut(kind);
+            let addr = segment.base + offset;
+            let entry = segment.page_table.get_mut(offset / FRAME_SIZE);
+            if entry.protection != Protection::ReadWrite {
+                return Err(Exception::IllegalMemoryAccess(addr))
+            }
+            entry.dirty = true;
+        }
+        self.prog_memory.write(mem_idx, val)
+    }
+    
+    /// Runs the instruction at `job.pc`, faulting its page in first if needed.
+    /// The instruction runs right after the fault so that a page can't be evicted
+    /// before its first use, which would livelock policies like LFU.
+    fn execute_instruction(&mut self, job: &mut Job) {
+        self.tlb.switch_to(job.pid, &job.filename);
+        self.clock += 1;
+        if let Some(pcb) = self.procs.get_mut(job.pid) {
+            pcb.state = ProcState::Running;
+            pcb.instructions += 1;
+        }
         
-        let limit = job.size;
-        let pt = &job.program.page_table;
-        let mut line_count = 0;
-
-        for page_idx in pt {
-            for _ in 0..FRAME_SIZE {
-
-                if limit == line_count {
-                    break
-                }
-
-                let line = self.prog_memory
-                    .read(find_mem_idx(page_idx.clone() as usize, job.pc))
-                    .split(';')
-                    .map(str::to_string)
-                    .collect();
-
-                line_count += 1;
-
-                interpreter(line, self);
-                job.pc += 1;
+        // Programs can run exec themselves, so the outer running job is restored afterwards.
+        // It is set before the fetch so that page faults are charged to the job.
+        let outer = self.running.replace(job.clone());
+        let line = self.access_memory(job, SegmentKind::Code, job.pc)
+            .and_then(|idx| self.prog_memory.read(idx));
+        match line {
+            Ok(line) => job.pc = control::step(self, job, &line),
+            Err(e) => {
+                // The job can't make progress, so it is terminated
+                let msg = format!("pid {} ({}) terminated on line {}, {e}", job.pid, job.filename, job.pc + 1);
+                err_msg(&self.out, msg.as_str());
+                job.status.set(FAILURE);
+                job.pc = job.size;
+            }
+        }
+        self.running = outer;
+        // A nested exec left the TLB to its jobs, the outer job takes it back for the rest of its line
+        if let Some(outer) = &self.running {
+            self.tlb.switch_to(outer.pid, &outer.filename);
+        }
+        
+        // The status was recorded as the job's by the interpreter, only the jump to the end is left
+        if self.exit_requested.take().is_some() {
+            job.pc = job.size;
+        }
+        let killed = self.procs.get(job.pid).is_some_and(|pcb| pcb.killed);
+        if killed {
+            job.pc = job.size;
+        }
+        self.procs.set_state(job.pid, ProcState::Ready);
+    }
+    
+    /// Collects the exit status of a terminated background job, None if it isn't done yet
+    pub(crate) fn reap_background(&mut self, pid: isize) -> Option<Status> {
+        let status = self.procs.reap(pid)?;
+        self.background.retain(|p| *p != pid);
+        Some(status)
+    }
+    
+    /// Lets every background job finish, like the shell does before it exits at the end of its input
+    pub(crate) fn wait_background(&mut self) {
+        let pids = self.background.clone();
+        if let Err(e) = self.execute_until(&pids).map_err(String::from) {
+            err_msg(&self.out, e.as_str());
+        }
+        for pid in pids {
+            self.reap_background(pid);
+        }
+    }
+    
+    /// Terminates a job, right away if it waits in the queue, otherwise once its current line is done
+    pub(crate) fn kill(&mut self, pid: isize) -> Result<(), String> {
+        let Some(pcb) = self.procs.get_mut(pid) else {
+            return Err(format!("no process with pid {pid}"))
+        };
+        if pcb.state == ProcState::Terminated {
+            return Err(format!("process {pid} has already terminated"))
+        }
+        pcb.killed = true;
+        if let Some(pos) = self.job_queue.iter().position(|job| job.pid == pid) {
+            if let Some(job) = self.job_queue.remove(pos) {
+                self.finish(job);
             }
         }
-        self.dealloc_program(job).expect("TODO: panic message");
+        Ok(())
+    }
+    
+    fn handle_page_fault(&mut self, program: &Rc<RefCell<Program>>, page: usize) -> Result<(), Exception> {
+        // When memory is full the eviction prints the victim's contents instead
+        if self.frame_table.find_free_frame().is_some() {
+            outln!(self.out, "Page fault!");
+        }
+        self.frame_table.faults += 1;
+        if let Some(pcb) = self.running.as_ref().and_then(|job| self.procs.get_mut(job.pid)) {
+            pcb.faults += 1;
+        }
+        self.load_page(program, page)
     }
 
-    pub(crate) fn memory_dump(&self) {
-        println!("-=-=-=-=-= Dumping Memory =-=-=-=-=-");
+    /// Prints the frames in use, with where each page sits in its program's segments
+    pub(crate) fn memory_dump(&self) -> Result<(), Exception> {
+        outln!(self.out, "-=-=-=-=-= Dumping Memory =-=-=-=-=-");
         for (i, f) in self.frame_table.frames.iter().enumerate() {
-            if f.valid {
-                println!("Frame {i}");
-                for j in 0..FRAME_SIZE {
-                    let line = self.prog_memory.read(i * FRAME_SIZE + j);
-                    println!("[{:02}]: {}", j, line);
+            if !f.valid {
+                continue
+            }
+            let Some(owner) = f.owner.upgrade() else {
+                continue
+            };
+            let program = owner.borrow();
+            let Some(segment) = program.segment_of(f.page) else {
+                continue
+            };
+            outln!(
+                self.out,
+                "Frame {}: {} {} page {} (base {}, limit {})",
+                i,
+                program.filename,
+                segment.kind,
+                f.page - segment.first_page(),
+                segment.base,
+                segment.limit
+            );
+            for j in 0..FRAME_SIZE {
+                let addr = f.page * FRAME_SIZE + j;
+                if addr == segment.end() {
+                    outln!(self.out, "----- end of {} segment -----", segment.kind);
                 }
+                let line = self.prog_memory.read(frame_mem_idx(i, j))?;
+                outln!(self.out, "[{:02}]: {}", addr, line);
             }
         }
+        Ok(())
+    }
+    
+    /// Prints the segment table of a program
+    pub(crate) fn segment_dump(&self, program: &Program) {
+        outln!(self.out, "{:<6}{:<6}{:<6}PAGES", "SEG", "BASE", "LIMIT");
+        for kind in SEGMENTS {
+            let segment = program.segment(kind);
+            // An empty segment, e.g. the code of an empty program, has no pages
+            let pages = match segment.num_pages() {
+                0 => String::from("-"),
+                n => format!("{}-{}", segment.first_page(), segment.first_page() + n - 1),
+            };
+            outln!(
+                self.out,
+                "{:<6}{:<6}{:<6}{}",
+                kind.to_string(),
+                segment.base,
+                segment.limit,
+                pages
+            );
+        }
     }
 }
-
@@ -3,47 +3,127 @@ mod shellmemory;
 mod kernel;
 mod job;
 mod errors;
+mod replacement;
+mod filesystem;
+mod blockdevice;
+mod pagetable;
+mod tlb;
+mod segment;
+mod heap;
+mod parser;
+mod control;
+mod output;
+mod proctable;
+#[cfg(test)]
+mod tests;
 
 use {
-    std::io::Write,
-    std::io,
+    std::io::{BufRead, IsTerminal, Write},
+    std::{env, fs, io, process},
 };
 use chrono::Local;
 
 fn main() {
-    println!("Minos Shell v0.0.0 - minsh");
+    // Commands come from a script given as argument or from stdin, only a terminal gets prompts
+    let args: Vec<String> = env::args().collect();
+    let (input, interactive): (Box<dyn BufRead>, bool) = match args.as_slice() {
+        [_] => (Box::new(io::stdin().lock()), io::stdin().is_terminal()),
+        [_, script] => match fs::File::open(script) {
+            Ok(f) => (Box::new(io::BufReader::new(f)), false),
+            Err(e) => {
+                eprintln!("minsh: {script}: {e}");
+                process::exit(127)
+            }
+        },
+        _ => {
+            eprintln!("usage: minos [SCRIPT]");
+            process::exit(2)
+        }
+    };
+    
+    if interactive {
+        println!("Minos Shell v0.0.0 - minsh");
+    }
     let var_mem = shellmemory::VarMemory::new(shellmemory::VAR_SIZE);
     let p_mem = shellmemory::ProgMemory::new(shellmemory::MEM_SIZE);
     let frame_t = shellmemory::FrameTable::new();
+    let tlb = tlb::Tlb::new(tlb::TLB_SIZE, tlb::TLB_WAYS, tlb::TlbPolicy::LRU, false);
+    let fs = mount(blockdevice::DISK_IMAGE, interactive).unwrap_or_else(|e| {
+        eprintln!("minsh: {e}");
+        eprintln!("minsh: remove {} to start with a new disk image", blockdevice::DISK_IMAGE);
+        process::exit(1)
+    });
     
     let mut kernel = kernel::Kernel::new(
         kernel::Mode::FCFS,
         p_mem,
         var_mem,
-        frame_t
+        frame_t,
+        tlb,
+        fs,
+        output::Output::stdout(),
     );
-    
-    let mut cwd = String::from("/");
+    if let Err(e) = kernel.init_backing_store() {
+        eprintln!("minsh: failed to set up the backing store: {e}");
+        process::exit(1)
+    }
     
     let prompt = '$';
+    let mut lines = input.lines();
     
     loop {
         
-        let mut buf = String::new();
-        let time = Local::now().format("%H:%M");
-        print!("{time}~{cwd} {prompt} ");
+        if interactive {
+            let time = Local::now().format("%H:%M");
+            let cwd = kernel.fs.pwd();
+            print!("{time}~{cwd} {prompt} ");
+            io::stdout().flush().expect("Terminated due to stdout flush error");
+        }
         
-        io::stdout().flush().expect("Terminated due to stdout flush error");
-        io::stdin().read_line(&mut buf).expect("Failed to read from stdin");
-
-        let args: Vec<String> = buf.split(';').map(str::to_string).collect();
+        // The shell ends at the end of its input like on `exit`, with the last status
+        let buf = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => {
+                eprintln!("minsh: failed to read the input: {e}");
+                process::exit(1)
+            }
+            None => {
+                if interactive {
+                    println!();
+                }
+                kernel.wait_background();
+                process::exit(kernel.status)
+            }
+        };
 
         interpreter::interpreter(
-            args,
+            &buf,
             &mut kernel,
         );
-        // kernel.get_mut_pmem().dump("ok", false);
-        // kernel.get_mut_ft().frame_dump();
+        if let Some(status) = kernel.exit_requested {
+            process::exit(status);
+        }
+        kernel.shell_yield();
+        // kernel.prog_memory.dump(&kernel.out);
+        // kernel.frame_table.frame_dump(&kernel.out);
         // kernel.memory_dump();
     }
 }
+
+/// Mounts the file system on the disk image, formatting the image if it is brand new
+fn mount(image: &str, verbose: bool) -> Result<filesystem::FileSystem, String> {
+    let mut disk = blockdevice::BlockDevice::open(image, blockdevice::NUM_BLOCKS)
+        .map_err(|e| format!("failed to open disk image {image}: {e}"))?;
+    
+    let blank = disk.is_blank()
+        .map_err(|e| format!("failed to read disk image {image}: {e}"))?;
+    let res = if blank {
+        if verbose {
+            println!("Formatting new disk image {image}");
+        }
+        filesystem::FileSystem::format(disk)
+    } else {
+        filesystem::FileSystem::mount(disk)
+    };
+    res.map_err(|e| format!("failed to mount disk image {image}: {e}"))
+}
@@ -0,0 +1,52 @@
+use std::{
+    cell::RefCell,
+    fmt::{self, Write},
+    rc::Rc,
+};
+
+/// Where the shell prints, stdout unless the output is captured, e.g. by the tests.
+/// Clones share the same sink.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Output {
+    captured: Option<Rc<RefCell<String>>>,
+}
+
+impl Output {
+    pub(crate) fn stdout() -> Output {
+        Output{captured: None}
+    }
+
+    /// An output kept in memory, read back with `take`
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn captured() -> Output {
+        Output{captured: Some(Rc::new(RefCell::new(String::new())))}
+    }
+
+    pub(crate) fn print(&self, args: fmt::Arguments) {
+        match &self.captured {
+            Some(buf) => buf.borrow_mut().write_fmt(args).expect("Failed to capture output"),
+            None => print!("{args}"),
+        }
+    }
+
+    /// Everything printed since the last call, always empty when printing to stdout
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn take(&self) -> String {
+        self.captured
+            .as_ref()
+            .map(|buf| std::mem::take(&mut *buf.borrow_mut()))
+            .unwrap_or_default()
+    }
+}
+
+/// `println!` to an `Output`
+macro_rules! outln {
+    ($out:expr) => {
+        $out.print(format_args!("\n"))
+    };
+    ($out:expr, $($arg:tt)*) => {
+        $out.print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+pub(crate) use outln;
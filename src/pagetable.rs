@@ -0,0 +1,250 @@
+use std::fmt;
+use crate::output::{outln, Output};
+
+pub(crate) const PAGE_TABLE_BITS: usize = 3; // Each table indexes 8 entries
+pub(crate) const ENTRIES_PER_TABLE: usize = 1 << PAGE_TABLE_BITS;
+pub(crate) const PAGE_TABLE_LEVELS: usize = 2;
+pub(crate) const MAX_PAGE_TABLE_LEVELS: usize = 4;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Protection {
+    ReadOnly,
+    ReadWrite,
+    ReadExecute,
+}
+
+impl fmt::Display for Protection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protection::ReadOnly => write!(f, "r--"),
+            Protection::ReadWrite => write!(f, "rw-"),
+            Protection::ReadExecute => write!(f, "r-x"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PageTableEntry {
+    pub(crate) frame: usize,
+    pub(crate) valid: bool,      // The page is part of the address space
+    pub(crate) present: bool,    // The page is in a frame
+    pub(crate) dirty: bool,      // The frame was written to since it was loaded
+    pub(crate) referenced: bool, // The page was used since it was loaded
+    pub(crate) protection: Protection,
+}
+
+impl PageTableEntry {
+    pub(crate) fn not_present() -> PageTableEntry {
+        PageTableEntry{
+            frame: 0,
+            valid: false,
+            present: false,
+            dirty: false,
+            referenced: false,
+            protection: Protection::ReadOnly,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct PageDirectoryEntry {
+    table: Option<Box<PageNode>>, // Tables are only allocated once a page under them is mapped
+    referenced: bool,
+}
+
+#[derive(Clone, Debug)]
+enum PageNode {
+    Directory(Vec<PageDirectoryEntry>),
+    Table(Vec<PageTableEntry>),
+}
+
+impl PageNode {
+    fn new(levels_below: usize) -> PageNode {
+        if levels_below == 0 {
+            PageNode::Table(vec![PageTableEntry::not_present(); ENTRIES_PER_TABLE])
+        } else {
+            let entry = PageDirectoryEntry{table: None, referenced: false};
+            PageNode::Directory(vec![entry; ENTRIES_PER_TABLE])
+        }
+    }
+
+    fn num_tables(&self) -> usize {
+        match self {
+            PageNode::Table(_) => 1,
+            PageNode::Directory(entries) => {
+                1 + entries.iter()
+                    .filter_map(|e| e.table.as_ref())
+                    .map(|t| t.num_tables())
+                    .sum::<usize>()
+            }
+        }
+    }
+}
+
+/// An N-level page table, the page number is split into one index per level,
+/// most significant bits first. The last level holds the page table entries.
+#[derive(Clone, Debug)]
+pub(crate) struct PageTable {
+    levels: usize,
+    root: PageNode,
+}
+
+impl PageTable {
+    pub(crate) fn new(levels: usize) -> PageTable {
+        PageTable{
+            levels,
+            root: PageNode::new(levels - 1),
+        }
+    }
+
+    /// Number of pages the table can address
+    pub(crate) fn capacity(&self) -> usize {
+        1 << (PAGE_TABLE_BITS * self.levels)
+    }
+
+    fn index(&self, page: usize, level: usize) -> usize {
+        (page >> (PAGE_TABLE_BITS * (self.levels - 1 - level))) & (ENTRIES_PER_TABLE - 1)
+    }
+
+    pub(crate) fn get(&self, page: usize) -> Option<&PageTableEntry> {
+        if page >= self.capacity() {
+            return None
+        }
+        let mut node = &self.root;
+        for level in 0..self.levels {
+            let idx = self.index(page, level);
+            match node {
+                PageNode::Directory(entries) => node = entries[idx].table.as_ref()?,
+                PageNode::Table(entries) => return Some(&entries[idx]),
+            }
+        }
+        None
+    }
+
+    /// Walks down to the entry of `page`, allocating the tables on the way.
+    /// When `reference` is set, every entry walked through gets its referenced bit set.
+    fn walk(&mut self, page: usize, reference: bool) -> &mut PageTableEntry {
+        assert!(page < self.capacity(), "Page {page} is outside of the address space");
+        let levels = self.levels;
+        let indices: Vec<usize> = (0..levels).map(|l| self.index(page, l)).collect();
+
+        let mut node = &mut self.root;
+        for (level, idx) in indices.into_iter().enumerate() {
+            match node {
+                PageNode::Directory(entries) => {
+                    let entry = &mut entries[idx];
+                    entry.referenced |= reference;
+                    node = entry.table.get_or_insert_with(|| {
+                        Box::new(PageNode::new(levels - level - 2))
+                    });
+                }
+                PageNode::Table(entries) => {
+                    let entry = &mut entries[idx];
+                    entry.referenced |= reference;
+                    return entry
+                }
+            }
+        }
+        unreachable!("Page table is deeper than its number of levels")
+    }
+
+    pub(crate) fn get_mut(&mut self, page: usize) -> &mut PageTableEntry {
+        self.walk(page, false)
+    }
+
+    /// Adds a page to the address space, it isn't present until it gets a frame
+    pub(crate) fn map(&mut self, page: usize, protection: Protection) {
+        let entry = self.get_mut(page);
+        *entry = PageTableEntry::not_present();
+        entry.valid = true;
+        entry.protection = protection;
+    }
+
+    /// Translates a page to the frame holding it, setting the referenced bits on the way
+    pub(crate) fn translate(&mut self, page: usize) -> Option<usize> {
+        if !self.get(page).is_some_and(|e| e.present) {
+            return None
+        }
+        Some(self.walk(page, true).frame)
+    }
+
+    /// Sets the referenced bits of a page without translating it, for accesses the TLB served
+    pub(crate) fn reference(&mut self, page: usize) {
+        self.walk(page, true);
+    }
+
+    /// Every valid page with its entry, in page order
+    pub(crate) fn pages(&self) -> Vec<(usize, PageTableEntry)> {
+        let mut pages = vec![];
+        Self::collect_pages(&self.root, 0, &mut pages);
+        pages
+    }
+
+    fn collect_pages(node: &PageNode, prefix: usize, pages: &mut Vec<(usize, PageTableEntry)>) {
+        match node {
+            PageNode::Table(entries) => {
+                for (idx, entry) in entries.iter().enumerate() {
+                    if entry.valid {
+                        pages.push(((prefix << PAGE_TABLE_BITS) | idx, *entry));
+                    }
+                }
+            }
+            PageNode::Directory(entries) => {
+                for (idx, entry) in entries.iter().enumerate() {
+                    if let Some(table) = &entry.table {
+                        Self::collect_pages(table, (prefix << PAGE_TABLE_BITS) | idx, pages);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Prints the allocated part of the tree, unallocated tables are skipped
+    pub(crate) fn dump(&self, out: &Output) {
+        let tables = self.root.num_tables();
+        outln!(
+            out,
+            "{} levels, {} tables of {} entries allocated ({} entries, a flat table would need {})",
+            self.levels,
+            tables,
+            ENTRIES_PER_TABLE,
+            tables * ENTRIES_PER_TABLE,
+            self.capacity()
+        );
+        Self::dump_node(&self.root, 0, 0, out);
+    }
+
+    fn dump_node(node: &PageNode, depth: usize, prefix: usize, out: &Output) {
+        let indent = "  ".repeat(depth);
+        match node {
+            PageNode::Directory(entries) => {
+                for (idx, entry) in entries.iter().enumerate() {
+                    if let Some(table) = &entry.table {
+                        let referenced = if entry.referenced { "R" } else { "-" };
+                        outln!(out, "{indent}pde[{idx}] {referenced}");
+                        Self::dump_node(table, depth + 1, (prefix << PAGE_TABLE_BITS) | idx, out);
+                    }
+                }
+            }
+            PageNode::Table(entries) => {
+                for (idx, entry) in entries.iter().enumerate().filter(|(_, e)| e.valid) {
+                    let page = (prefix << PAGE_TABLE_BITS) | idx;
+                    let frame = if entry.present {
+                        format!("frame {}", entry.frame)
+                    } else {
+                        String::from("on disk")
+                    };
+                    outln!(
+                        out,
+                        "{indent}pte[{idx}] page {page:<3} {frame:<9} {}{}{} {}",
+                        if entry.present { "P" } else { "-" },
+                        if entry.dirty { "D" } else { "-" },
+                        if entry.referenced { "R" } else { "-" },
+                        entry.protection
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use crate::shellmemory::VarMemory;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum WordPart {
+    Literal(String), // Quoted with single quotes or escaped, never expanded
+    Expand(String),  // Bare or in double quotes, variables get substituted
+}
+
+/// A word of a command before expansion, made of the pieces it was quoted in
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Word {
+    parts: Vec<WordPart>,
+    quoted: bool, // A quoted word stays even if it expands to nothing
+}
+
+impl Word {
+    fn push(&mut self, c: char, literal: bool) {
+        match (self.parts.last_mut(), literal) {
+            (Some(WordPart::Literal(s)), true) | (Some(WordPart::Expand(s)), false) => s.push(c),
+            (_, true) => self.parts.push(WordPart::Literal(String::from(c))),
+            (_, false) => self.parts.push(WordPart::Expand(String::from(c))),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.parts.is_empty() && !self.quoted
+    }
+
+    /// The word as written if it is bare text, so keywords can't be quoted or made of variables
+    pub(crate) fn as_plain(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Expand(s)] if !self.quoted && !s.contains('$') => Some(s),
+            _ => None
+        }
+    }
+
+    /// Substitutes the variables of the word, None when an unquoted word expands to nothing.
+    /// `status` is what `$?` expands to.
+    pub(crate) fn expand(&self, var_mem: &VarMemory, status: i32) -> Option<String> {
+        let mut res = String::new();
+        for part in self.parts.iter() {
+            match part {
+                WordPart::Literal(s) => res.push_str(s),
+                WordPart::Expand(s) => res.push_str(&expand_vars(s, var_mem, status)),
+            }
+        }
+        if res.is_empty() && !self.quoted {
+            return None
+        }
+        Some(res)
+    }
+}
+
+/// How a command is chained to the one before it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Connector {
+    Seq, // `;` or the first command, always runs
+    And, // `&&`, runs if the previous command succeeded
+    Or,  // `||`, runs if the previous command failed
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Command {
+    pub(crate) connector: Connector,
+    pub(crate) words: Vec<Word>,
+    pub(crate) background: bool, // Ended by `&` or a lone `#`, only `exec` runs in the background
+}
+
+/// Splits a line into commands. Quotes, backslash escapes and `#` comments are handled here,
+/// variables are expanded later, right before each command runs.
+pub(crate) fn parse(line: &str) -> Result<Vec<Command>, String> {
+    let mut commands = vec![];
+    let mut words: Vec<Word> = vec![];
+    let mut word = Word::default();
+    let mut connector = Connector::Seq;
+    let mut background = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' | '\r' => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            '#' if word.is_empty() => {
+                // A lone `#` ending a command is how the course's shell ran programs in the background
+                background = !words.is_empty() && chars.all(char::is_whitespace);
+                break
+            }
+            ';' | '&' | '|' => {
+                let next = match c {
+                    ';' => Connector::Seq,
+                    _ if chars.peek() == Some(&c) => {
+                        chars.next();
+                        if c == '&' { Connector::And } else { Connector::Or }
+                    }
+                    // A single `&` ends a command that runs in the background, pipes aren't supported
+                    '&' => {
+                        background = true;
+                        Connector::Seq
+                    }
+                    _ => return Err(String::from("syntax error near `|`, pipes are not supported")),
+                };
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+
+                if words.is_empty() {
+                    // Empty statements like `;;` are skipped, but `&&`, `||` and `&` need a command before them
+                    if background {
+                        return Err(String::from("syntax error near `&`"))
+                    }
+                    if next != Connector::Seq || connector != Connector::Seq {
+                        return Err(format!("syntax error near `{}`", separator(next)))
+                    }
+                    continue
+                }
+                commands.push(Command{connector, words: std::mem::take(&mut words), background});
+                connector = next;
+                background = false;
+            }
+            '\'' => {
+                word.quoted = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c, true),
+                        None => return Err(String::from("unterminated quote `'`")),
+                    }
+                }
+            }
+            '"' => {
+                word.quoted = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(e @ ('"' | '\\' | '$')) => word.push(e, true),
+                            Some(e) => {
+                                word.push('\\', false);
+                                word.push(e, false);
+                            }
+                            None => return Err(String::from("unterminated quote `\"`")),
+                        },
+                        Some(c) => word.push(c, false),
+                        None => return Err(String::from("unterminated quote `\"`")),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(e) => word.push(e, true),
+                None => word.push('\\', true),
+            },
+            _ => word.push(c, false),
+        }
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+    if words.is_empty() {
+        if connector != Connector::Seq {
+            return Err(format!("syntax error, nothing after `{}`", separator(connector)))
+        }
+    } else {
+        commands.push(Command{connector, words, background});
+    }
+    Ok(commands)
+}
+
+fn separator(connector: Connector) -> &'static str {
+    match connector {
+        Connector::Seq => ";",
+        Connector::And => "&&",
+        Connector::Or => "||",
+    }
+}
+
+/// Substitutes `$NAME` and `${NAME}` with the value of the variable, or nothing if it isn't set.
+/// `$?` is the status of the last command and `$#` the number of arguments of a function.
+fn expand_vars(text: &str, var_mem: &VarMemory, status: i32) -> String {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut res = String::new();
+    let mut rest = text;
+    while let Some(pos) = rest.find('$') {
+        res.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        let (name, next) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", after),
+            }
+        } else if let Some(next) = after.strip_prefix('?') {
+            ("?", next)
+        } else if let Some(next) = after.strip_prefix('#') {
+            ("#", next)
+        } else {
+            let end = after.find(|c: char| !is_name(c)).unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+
+        // A lone `$` is kept as is
+        if name.is_empty() {
+            res.push('$');
+        } else if name == "?" {
+            res.push_str(&status.to_string());
+        } else {
+            res.push_str(&var_mem.get(name).unwrap_or_default());
+        }
+        rest = next;
+    }
+    res.push_str(rest);
+    res
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+};
+use crate::interpreter::Status;
+
+/// Status of a job that was killed, like a process ended by SIGKILL
+pub(crate) const KILLED: Status = 137;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ProcState {
+    New,        // Created, not queued yet
+    Ready,      // Waiting in the job queue for its turn
+    Running,    // Running an instruction
+    Blocked,    // Waiting for the programs it started with exec
+    Terminated, // Done, kept as a zombie until its status is collected
+}
+
+impl fmt::Display for ProcState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ProcState::New => "NEW",
+            ProcState::Ready => "READY",
+            ProcState::Running => "RUNNING",
+            ProcState::Blocked => "BLOCKED",
+            ProcState::Terminated => "TERMINATED",
+        };
+        // Padding is left to the caller, so the name can sit in a table
+        f.pad(name)
+    }
+}
+
+/// Process control block, what the kernel knows about a job besides its memory
+#[derive(Clone, Debug)]
+pub(crate) struct Pcb {
+    pub(crate) pid: isize,
+    pub(crate) ppid: Option<isize>, // None when started from the interactive shell
+    pub(crate) filename: String,
+    pub(crate) state: ProcState,
+    pub(crate) start: usize,        // Kernel clock when the job was created
+    pub(crate) instructions: usize,
+    pub(crate) faults: usize,
+    pub(crate) status: Option<Status>, // Exit status, once terminated
+    pub(crate) killed: bool,           // Killed while running or blocked, it ends after its current line
+}
+
+/// Every job that isn't reaped yet, by pid
+#[derive(Debug, Default)]
+pub(crate) struct ProcessTable {
+    pcbs: BTreeMap<isize, Pcb>,
+}
+
+impl ProcessTable {
+    pub(crate) fn create(&mut self, pid: isize, ppid: Option<isize>, filename: &str, start: usize) {
+        self.pcbs.insert(pid, Pcb{
+            pid,
+            ppid,
+            filename: String::from(filename),
+            state: ProcState::New,
+            start,
+            instructions: 0,
+            faults: 0,
+            status: None,
+            killed: false,
+        });
+    }
+
+    pub(crate) fn get(&self, pid: isize) -> Option<&Pcb> {
+        self.pcbs.get(&pid)
+    }
+
+    pub(crate) fn get_mut(&mut self, pid: isize) -> Option<&mut Pcb> {
+        self.pcbs.get_mut(&pid)
+    }
+
+    pub(crate) fn set_state(&mut self, pid: isize, state: ProcState) {
+        if let Some(pcb) = self.pcbs.get_mut(&pid) {
+            pcb.state = state;
+        }
+    }
+
+    pub(crate) fn terminate(&mut self, pid: isize, status: Status) {
+        if let Some(pcb) = self.pcbs.get_mut(&pid) {
+            pcb.state = ProcState::Terminated;
+            pcb.status = Some(status);
+        }
+    }
+
+    /// Removes a terminated job from the table and returns its exit status
+    pub(crate) fn reap(&mut self, pid: isize) -> Option<Status> {
+        if self.pcbs.get(&pid)?.state != ProcState::Terminated {
+            return None
+        }
+        self.pcbs.remove(&pid)?.status
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Pcb> {
+        self.pcbs.values()
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::{HashMap, VecDeque};
+
+pub(crate) const POLICIES: [&str; 5] = ["FIFO", "LRU", "CLOCK", "LFU", "OPT"];
+
+/// Decides which frame gets evicted when memory is full.
+/// Pages are identified by an id unique to a (program, page) pair.
+pub(crate) trait ReplacementPolicy {
+    fn name(&self) -> &'static str;
+    /// A page was just brought into `frame`
+    fn loaded(&mut self, frame: usize, page: usize);
+    /// The page held by `frame` was referenced
+    fn accessed(&mut self, frame: usize, page: usize);
+    /// `frame` no longer holds a page
+    fn freed(&mut self, frame: usize);
+    /// Picks the frame to evict among the ones holding a page
+    fn victim(&mut self) -> Option<usize>;
+}
+
+/// Builds a policy by name, the reference string is only used by OPT
+pub(crate) fn new_policy(
+    name: &str,
+    num_frames: usize,
+    refs: &[usize],
+) -> Option<Box<dyn ReplacementPolicy>> {
+    match name {
+        "FIFO" => Some(Box::new(Fifo::new())),
+        "LRU" => Some(Box::new(Lru::new())),
+        "CLOCK" => Some(Box::new(Clock::new(num_frames))),
+        "LFU" => Some(Box::new(Lfu::new())),
+        "OPT" => Some(Box::new(Optimal::new(refs.to_vec()))),
+        _ => None
+    }
+}
+
+/// Replays a reference string against a policy and returns the number of page faults
+pub(crate) fn simulate(
+    policy: &mut dyn ReplacementPolicy,
+    refs: &[usize],
+    num_frames: usize,
+) -> usize {
+    let mut resident: HashMap<usize, usize> = HashMap::new(); // page -> frame
+    let mut free: Vec<usize> = (0..num_frames).rev().collect();
+    let mut faults = 0;
+
+    for &page in refs {
+        let frame = match resident.get(&page) {
+            Some(f) => *f,
+            None => {
+                faults += 1;
+                let frame = match free.pop() {
+                    Some(f) => f,
+                    None => {
+                        let victim = policy.victim().expect("Memory is full but the policy has no victim");
+                        policy.freed(victim);
+                        resident.retain(|_, f| *f != victim);
+                        victim
+                    }
+                };
+                policy.loaded(frame, page);
+                resident.insert(page, frame);
+                frame
+            }
+        };
+        policy.accessed(frame, page);
+    }
+    faults
+}
+
+pub(crate) struct Fifo {
+    queue: VecDeque<usize>,
+}
+
+impl Fifo {
+    pub(crate) fn new() -> Fifo {
+        Fifo{queue: VecDeque::new()}
+    }
+}
+
+impl ReplacementPolicy for Fifo {
+    fn name(&self) -> &'static str { "FIFO" }
+
+    fn loaded(&mut self, frame: usize, _page: usize) {
+        self.queue.push_back(frame);
+    }
+
+    fn accessed(&mut self, _frame: usize, _page: usize) {}
+
+    fn freed(&mut self, frame: usize) {
+        self.queue.retain(|f| *f != frame);
+    }
+
+    fn victim(&mut self) -> Option<usize> {
+        self.queue.front().copied()
+    }
+}
+
+pub(crate) struct Lru {
+    // Least recently used at the front
+    queue: VecDeque<usize>,
+}
+
+impl Lru {
+    pub(crate) fn new() -> Lru {
+        Lru{queue: VecDeque::new()}
+    }
+}
+
+impl ReplacementPolicy for Lru {
+    fn name(&self) -> &'static str { "LRU" }
+
+    fn loaded(&mut self, frame: usize, _page: usize) {
+        self.queue.push_back(frame);
+    }
+
+    fn accessed(&mut self, frame: usize, _page: usize) {
+        if let Some(pos) = self.queue.iter().position(|f| *f == frame) {
+            self.queue.remove(pos);
+        }
+        self.queue.push_back(frame);
+    }
+
+    fn freed(&mut self, frame: usize) {
+        self.queue.retain(|f| *f != frame);
+    }
+
+    fn victim(&mut self) -> Option<usize> {
+        self.queue.front().copied()
+    }
+}
+
+/// Second chance: the hand sweeps the frames, clearing reference bits until it finds one unset
+pub(crate) struct Clock {
+    referenced: Vec<Option<bool>>, // None when the frame holds no page
+    hand: usize,
+}
+
+impl Clock {
+    pub(crate) fn new(num_frames: usize) -> Clock {
+        Clock{
+            referenced: vec![None; num_frames],
+            hand: 0,
+        }
+    }
+}
+
+impl ReplacementPolicy for Clock {
+    fn name(&self) -> &'static str { "CLOCK" }
+
+    fn loaded(&mut self, frame: usize, _page: usize) {
+        self.referenced[frame] = Some(false);
+    }
+
+    fn accessed(&mut self, frame: usize, _page: usize) {
+        self.referenced[frame] = Some(true);
+    }
+
+    fn freed(&mut self, frame: usize) {
+        self.referenced[frame] = None;
+    }
+
+    fn victim(&mut self) -> Option<usize> {
+        if self.referenced.iter().all(Option::is_none) {
+            return None
+        }
+        loop {
+            let frame = self.hand;
+            self.hand = (self.hand + 1) % self.referenced.len();
+            match self.referenced[frame] {
+                Some(true) => self.referenced[frame] = Some(false),
+                Some(false) => return Some(frame),
+                None => {}
+            }
+        }
+    }
+}
+
+/// Least frequently used, ties go to the page loaded first
+pub(crate) struct Lfu {
+    counts: HashMap<usize, (usize, usize)>, // frame -> (uses, load order)
+    loads: usize,
+}
+
+impl Lfu {
+    pub(crate) fn new() -> Lfu {
+        Lfu{
+            counts: HashMap::new(),
+            loads: 0,
+        }
+    }
+}
+
+impl ReplacementPolicy for Lfu {
+    fn name(&self) -> &'static str { "LFU" }
+
+    fn loaded(&mut self, frame: usize, _page: usize) {
+        self.counts.insert(frame, (0, self.loads));
+        self.loads += 1;
+    }
+
+    fn accessed(&mut self, frame: usize, _page: usize) {
+        if let Some((uses, _)) = self.counts.get_mut(&frame) {
+            *uses += 1;
+        }
+    }
+
+    fn freed(&mut self, frame: usize) {
+        self.counts.remove(&frame);
+    }
+
+    fn victim(&mut self) -> Option<usize> {
+        self.counts
+            .iter()
+            .min_by_key(|(_, count)| **count)
+            .map(|(frame, _)| *frame)
+    }
+}
+
+/// Belady's optimal policy: evicts the page whose next use is furthest in the future.
+/// It needs the whole reference string ahead of time, so it only works offline.
+pub(crate) struct Optimal {
+    refs: Vec<usize>,
+    cursor: usize,
+    resident: HashMap<usize, usize>, // frame -> page
+}
+
+impl Optimal {
+    pub(crate) fn new(refs: Vec<usize>) -> Optimal {
+        Optimal{
+            refs,
+            cursor: 0,
+            resident: HashMap::new(),
+        }
+    }
+
+    fn next_use(&self, page: usize) -> usize {
+        self.refs[self.cursor..]
+            .iter()
+            .position(|p| *p == page)
+            .unwrap_or(usize::MAX)
+    }
+}
+
+impl ReplacementPolicy for Optimal {
+    fn name(&self) -> &'static str { "OPT" }
+
+    fn loaded(&mut self, frame: usize, page: usize) {
+        self.resident.insert(frame, page);
+    }
+
+    fn accessed(&mut self, _frame: usize, _page: usize) {
+        self.cursor += 1;
+    }
+
+    fn freed(&mut self, frame: usize) {
+        self.resident.remove(&frame);
+    }
+
+    fn victim(&mut self) -> Option<usize> {
+        self.resident
+            .iter()
+            .max_by_key(|(frame, page)| (self.next_use(**page), **frame))
+            .map(|(frame, _)| *frame)
+    }
+}
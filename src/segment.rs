@@ -0,0 +1,85 @@
+use std::fmt;
+use crate::errors::Exception;
+use crate::pagetable::{PageTable, Protection};
+use crate::shellmemory::FRAME_SIZE;
+
+pub(crate) const DATA_SEGMENT_SIZE: usize = 16; // The heap, a power of two so the buddy allocator can use all of it
+pub(crate) const STACK_SEGMENT_SIZE: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SegmentKind {
+    Code,
+    Data,
+    Stack,
+}
+
+pub(crate) const SEGMENTS: [SegmentKind; 3] = [SegmentKind::Code, SegmentKind::Data, SegmentKind::Stack];
+
+impl fmt::Display for SegmentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentKind::Code => write!(f, "code"),
+            SegmentKind::Data => write!(f, "data"),
+            SegmentKind::Stack => write!(f, "stack"),
+        }
+    }
+}
+
+/// A segment of a program's address space. `base` and `limit` are in lines, the base is
+/// where the segment starts in the program's linear address space and is always page aligned.
+/// Each segment is paged through its own page table.
+#[derive(Clone, Debug)]
+pub(crate) struct Segment {
+    pub(crate) kind: SegmentKind,
+    pub(crate) base: usize,
+    pub(crate) limit: usize,
+    pub(crate) page_table: PageTable,
+}
+
+impl Segment {
+    pub(crate) fn new(
+        kind: SegmentKind,
+        base: usize,
+        limit: usize,
+        levels: usize,
+        protection: Protection,
+    ) -> Result<Segment, String> {
+        let mut page_table = PageTable::new(levels);
+        let num_pages = limit.div_ceil(FRAME_SIZE);
+        if num_pages > page_table.capacity() {
+            return Err(format!(
+                "{kind} segment is too large, a {levels}-level page table holds at most {} lines",
+                page_table.capacity() * FRAME_SIZE
+            ))
+        }
+        for page in 0..num_pages {
+            page_table.map(page, protection);
+        }
+        Ok(Segment{kind, base, limit, page_table})
+    }
+
+    /// Checks an offset against the limit register and turns it into a linear address
+    pub(crate) fn linear(&self, offset: usize) -> Result<usize, Exception> {
+        if offset >= self.limit {
+            return Err(Exception::IllegalMemoryAccess(self.base + offset))
+        }
+        Ok(self.base + offset)
+    }
+
+    pub(crate) fn first_page(&self) -> usize {
+        self.base / FRAME_SIZE
+    }
+
+    pub(crate) fn num_pages(&self) -> usize {
+        self.limit.div_ceil(FRAME_SIZE)
+    }
+
+    pub(crate) fn contains_page(&self, page: usize) -> bool {
+        (self.first_page()..self.first_page() + self.num_pages()).contains(&page)
+    }
+
+    /// One past the last linear address of the segment
+    pub(crate) fn end(&self) -> usize {
+        self.base + self.limit
+    }
+}
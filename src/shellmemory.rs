@@ -1,10 +1,20 @@
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
+    rc::Weak,
+};
+use crate::errors::Exception;
+use crate::job::Program;
+use crate::output::{outln, Output};
+use crate::replacement::{ReplacementPolicy, Lru};
 
 pub const FRAME_SIZE: usize = 4;
 pub const DEMAND_PAGE_LIMIT: usize = 2;
 pub const MEM_SIZE: usize = 80;
 pub const VAR_SIZE: usize = 100;
 pub const NUM_FRAMES: usize = MEM_SIZE / FRAME_SIZE;
+/// How many of the latest page references are kept for pagestat to replay
+pub const REFERENCE_WINDOW: usize = 4096;
 
 #[derive(Debug)]
 pub struct VarMemory {
@@ -21,28 +31,59 @@ impl VarMemory {
     }
     
     pub(crate) fn get(&self, key: &str) -> Option<String> {
-        for ent in &self.var_mem {
-            if let Some(ref k) = ent.key {
-                if k == key {
-                    let str = ent.val.clone();
-                    if let Some(s) = str {
-                        return Some(s)
-                    }
-                    panic!("fatal: could not allocate space for a variable ")
-                }
+        self.var_mem
+            .iter()
+            .find(|e| e.key.as_deref() == Some(key))
+            .and_then(|e| e.val.clone())
+    }
+    
+    /// Sets a variable, overwriting it if it exists or taking the first free slot otherwise
+    pub(crate) fn set(&mut self, key: String, val: String) -> Result<(), Exception> {
+        if let Some(ent) = self.var_mem.iter_mut().find(|e| e.key.as_ref() == Some(&key)) {
+            ent.val = Some(val);
+            return Ok(())
+        }
+        match self.var_mem.iter_mut().find(|e| e.key.is_none()) {
+            Some(ent) => {
+                ent.key = Some(key);
+                ent.val = Some(val);
+                Ok(())
+            }
+            None => Err(Exception::OutOfMemory(
+                format!("all {} variable slots are in use, could not set {}", self.size, key)
+            ))
+        }
+    }
+    
+    /// Removes a variable, freeing its slot. Returns whether it existed
+    pub(crate) fn unset(&mut self, key: &str) -> bool {
+        match self.var_mem.iter_mut().find(|e| e.key.as_deref() == Some(key)) {
+            Some(ent) => {
+                *ent = VarEntry::new();
+                true
             }
+            None => false
         }
-        None
     }
     
-    pub(crate) fn set(&mut self, key: String, val: String) {
+    /// Marks a variable to be inherited by the processes started from this one
+    pub(crate) fn export(&mut self, key: &str) -> bool {
         for ent in self.var_mem.iter_mut() {
-            if ent.key == None {
-                ent.key = Some(key);
-                ent.val = Some(val);
-                return
+            if ent.key.as_deref() == Some(key) {
+                ent.exported = true;
+                return true
             }
         }
+        false
+    }
+    
+    /// A new namespace for a child process, holding the exported variables only
+    pub(crate) fn inherit(&self) -> VarMemory {
+        let mut child = VarMemory::new(self.size);
+        for (slot, ent) in child.var_mem.iter_mut().zip(self.var_mem.iter().filter(|e| e.exported)) {
+            *slot = ent.clone();
+        }
+        child
     }
 }
 
@@ -50,6 +91,7 @@ impl VarMemory {
 struct VarEntry {
     key: Option<String>,
     val: Option<String>,
+    exported: bool,
 }
 
 impl VarEntry {
@@ -57,15 +99,18 @@ impl VarEntry {
         VarEntry{
             key: None, 
             val: None,
+            exported: false,
         }
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug)]
 pub struct Frame {
     pub(crate) valid: bool,  // If valid, then in-use; if not valid, then free
     id: usize,
-    program_id: String
+    program_id: String,
+    pub(crate) page: usize,
+    pub(crate) owner: Weak<RefCell<Program>>,
 }
 
 impl Frame {
@@ -73,77 +118,148 @@ impl Frame {
         Frame{
             valid: false,
             id,
-            program_id: String::from("OWNERLESS")
+            program_id: String::from("OWNERLESS"),
+            page: 0,
+            owner: Weak::new(),
         }
     }
     
     pub(crate) fn set_invalid(&mut self) {
         self.valid = false;
-        self.program_id = String::from("OWNERLESS")
+        self.program_id = String::from("OWNERLESS");
+        self.page = 0;
+        self.owner = Weak::new();
     }
     
-    pub(crate) fn set_valid(&mut self, program_id: String) {
+    pub(crate) fn set_valid(&mut self, program_id: String, page: usize, owner: Weak<RefCell<Program>>) {
         self.valid = true;
         self.program_id = program_id;
+        self.page = page;
+        self.owner = owner;
     }
 }
 
 pub struct FrameTable {
     pub(crate) frames: Vec<Frame>,
+    pub(crate) policy: Box<dyn ReplacementPolicy>,
+    pub(crate) faults: usize,
+    pub(crate) references: VecDeque<usize>, // The latest page ids in the order they were accessed
+    page_ids: HashMap<(String, usize), usize>, // Pages of the programs still loaded
+    next_page_id: usize, // Ids aren't reused, the references may still hold those of freed programs
 }
 
 impl FrameTable {
     pub(crate) fn new() -> FrameTable {
-        let mut vec: Vec<Frame> = vec![];
-        for i in 0..NUM_FRAMES {
-            vec.push(Frame::new(i));
+        FrameTable{
+            frames: (0..NUM_FRAMES).map(Frame::new).collect(),
+            policy: Box::new(Lru::new()),
+            faults: 0,
+            references: VecDeque::new(),
+            page_ids: HashMap::new(),
+            next_page_id: 0,
+        }
+    }
+    
+    pub(crate) fn find_free_frame(&self) -> Option<usize> {
+        self.frames.iter().position(|frame| !frame.valid)
+    }
+    
+    /// Identifies a page across programs, so policies can tell pages apart
+    fn page_id(&mut self, program_id: &str, page: usize) -> usize {
+        let next = &mut self.next_page_id;
+        *self.page_ids
+            .entry((String::from(program_id), page))
+            .or_insert_with(|| {
+                *next += 1;
+                *next - 1
+            })
+    }
+    
+    /// Drops the page ids of a program that was freed
+    pub(crate) fn forget_program(&mut self, program_id: &str) {
+        self.page_ids.retain(|(id, _), _| id != program_id);
+    }
+    
+    /// How many different pages the kept references are to
+    pub(crate) fn num_pages_referenced(&self) -> usize {
+        self.references.iter().collect::<HashSet<_>>().len()
+    }
+    
+    /// Hands a frame over to a page of a program
+    pub(crate) fn assign(
+        &mut self,
+        idx: usize,
+        program_id: String,
+        page: usize,
+        owner: Weak<RefCell<Program>>,
+    ) {
+        let id = self.page_id(&program_id, page);
+        self.frames[idx].set_valid(program_id, page, owner);
+        self.policy.loaded(idx, id);
+    }
+    
+    /// Records a reference to the page held by a frame
+    pub(crate) fn access(&mut self, idx: usize) {
+        let frame = &self.frames[idx];
+        let (program_id, page) = (frame.program_id.clone(), frame.page);
+        let id = self.page_id(&program_id, page);
+        if self.references.len() == REFERENCE_WINDOW {
+            self.references.pop_front();
         }
-        FrameTable{frames: vec}
+        self.references.push_back(id);
+        self.policy.accessed(idx, id);
+    }
+    
+    pub(crate) fn free(&mut self, idx: usize) {
+        self.frames[idx].set_invalid();
+        self.policy.freed(idx);
     }
     
-    fn find_free_frame(&self) -> isize {
-        for (idx, frame) in self.frames.iter().enumerate() {
-            if !frame.valid {
-                return idx as isize
+    /// Frees a frame and marks the page it held as no longer present for its owner
+    pub(crate) fn evict(&mut self, idx: usize) {
+        let frame = &self.frames[idx];
+        if let Some(owner) = frame.owner.upgrade() {
+            if let Some(entry) = owner.borrow_mut().page_entry(frame.page) {
+                entry.present = false;
             }
         }
-        -1
-    }
-    
-    pub(crate) fn alloc_frames(&mut self, num_frames: usize, filename: String) -> Vec<isize> {
-        let mut free: Vec<isize> = vec![];
-        for _ in 0..num_frames {
-            let idx = self.find_free_frame();
-            if idx != -1 {
-                free.push(idx);
-                let frame = self.frames.get_mut(idx as usize);
-                if let Some(frame) = frame {
-                    frame.valid = true;
-                    frame.program_id = filename.clone();
-                } else {
-                    let err = format!("Error retrieving frame: {:?}", frame);
-                    panic!("{err}")
-                }
-            } else {
-                panic!("TODO: Implement page replacement")
+        self.free(idx);
+    }
+    
+    pub(crate) fn select_victim(&mut self) -> Result<usize, Exception> {
+        self.policy.victim().ok_or(Exception::IllegalKernelState(
+            String::from("memory is full but the replacement policy has no victim")
+        ))
+    }
+    
+    /// Switches policies, the frames in use are handed to the new one in frame order
+    pub(crate) fn set_policy(&mut self, policy: Box<dyn ReplacementPolicy>) {
+        self.policy = policy;
+        for idx in 0..self.frames.len() {
+            if self.frames[idx].valid {
+                let frame = &self.frames[idx];
+                let (program_id, page) = (frame.program_id.clone(), frame.page);
+                let id = self.page_id(&program_id, page);
+                self.policy.loaded(idx, id);
             }
         }
-        free
+        self.faults = 0;
     }
     
-    pub fn frame_dump(&self) {
-        println!("===== FRAME DUMP =====");
+    /// Debugging aid, prints which program holds each frame
+    #[allow(dead_code)]
+    pub fn frame_dump(&self, out: &Output) {
+        outln!(out, "===== FRAME DUMP =====");
         let mut skipped = 0;
         for entry in self.frames.iter() {
             if entry.valid {
-                println!("[{}]: {}", entry.id, entry.program_id.clone());
+                outln!(out, "[{}]: {}", entry.id, entry.program_id.clone());
             } else {
                 skipped += 1;
             }
         }
-        println!("Skipped [{skipped}] empty FRAMES");
+        outln!(out, "Skipped [{skipped}] empty FRAMES");
     }
-    
 }
 
 #[derive(Debug)]
@@ -160,59 +276,43 @@ impl ProgMemory {
         }
     }
     
-    pub(crate) fn read(&self, idx: usize) -> String {
-        if idx > self.size {
-            let size = self.size;
-            self.dump(format!("Write out of bounds: {idx} when valid range is [0, {size}").as_str(), true);
-            panic!()
+    fn check(&self, idx: usize) -> Result<(), Exception> {
+        if idx >= self.size {
+            return Err(Exception::IllegalMemoryAccess(idx))
         }
-        self.prog_mem[idx].line.clone()
+        Ok(())
     }
     
-    pub(crate) fn write(&mut self, idx: usize, val: String) {
-        if idx > self.size {
-            let size = self.size;
-            self.dump(format!("Write out of bounds: {idx} when valid range is [0, {size}").as_str(), true);
-            panic!()
-        }
-        self.prog_mem[idx].line = val;
+    pub(crate) fn read(&self, idx: usize) -> Result<String, Exception> {
+        self.check(idx)?;
+        Ok(self.prog_mem[idx].line.clone())
     }
     
-    pub(crate) fn write_to_frame(&mut self, frame_idx: usize, offset: usize, val: String) {
-        let idx = frame_idx * FRAME_SIZE + offset;
-        if idx > self.size {
-            let size = self.size;
-            self.dump(format!("Write out of bounds: {idx} when valid range is [0, {size}").as_str(), true);
-            panic!()
-        }
+    pub(crate) fn write(&mut self, idx: usize, val: String) -> Result<(), Exception> {
+        self.check(idx)?;
         self.prog_mem[idx].line = val;
+        Ok(())
     }
-
-    pub(crate) fn read_from_frame() {
-
+    
+    pub(crate) fn write_to_frame(&mut self, frame_idx: usize, offset: usize, val: String) -> Result<(), Exception> {
+        self.write(frame_idx * FRAME_SIZE + offset, val)
     }
     
-    pub(crate) fn dump(&self, msg: &str, fatal: bool) {
-        if fatal {
-            println!("\n===== FATAL ERROR! =====");
-            println!("ERROR: {}", msg);
-        }
-        println!("===== MEMORY DUMP =====");
-        println!("Starting at index 0 below");
+    /// Debugging aid, prints every line in memory
+    #[allow(dead_code)]
+    pub(crate) fn dump(&self, out: &Output) {
+        outln!(out, "===== MEMORY DUMP =====");
+        outln!(out, "Starting at index 0 below");
         // Iterate through memory contents and print them
         let mut skipped = 0;
         for (index, entry) in self.prog_mem.iter().enumerate() {
             if !entry.line.is_empty() {
-                println!("[{:04}]: {}", index, entry.line);
+                outln!(out, "[{:04}]: {}", index, entry.line);
             } else {
                 skipped += 1;
             }
         }
-        println!("Skipped [{skipped}] empty lines");
-        
-        if fatal {
-            panic!("An error occurred while attempting a memory operation.")
-        }
+        outln!(out, "Skipped [{skipped}] empty lines");
     }
 }
 
@@ -225,10 +325,6 @@ impl ProgEntry {
     pub(crate) fn new() -> ProgEntry {
         ProgEntry{line: String::new()}
     }
-    
-    pub(crate) fn reset(&mut self) {
-        self.line = String::new()
-    }
 }
 
 
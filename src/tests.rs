@@ -0,0 +1,134 @@
+//! Golden output tests. Every `tests/scripts/NAME.txt` is run through a fresh shell, line by line
+//! like in batch mode, and what it prints must match `tests/scripts/NAME_result.txt`.
+//! Run with `BLESS=1 cargo test` to write the expected output of new or changed scripts.
+//! Scripts run from the crate root, so they can import the programs in `tests/programs`.
+//! A `#! damage ...` line, a comment to the shell, breaks the disk for the fsck tests, see `FileSystem::damage`.
+
+use std::{env, fs, path::{Path, PathBuf}, process};
+use crate::{interpreter, mount, shellmemory, tlb};
+use crate::blockdevice::BLOCK_SIZE;
+use crate::kernel::{Kernel, Mode};
+use crate::output::Output;
+
+const SCRIPTS: &str = "tests/scripts";
+const RESULT_SUFFIX: &str = "_result.txt";
+
+/// Boots a shell on its own blank disk image and runs a script, returning what it printed
+fn run_script(script: &Path) -> String {
+    let name = script.file_stem().unwrap_or_default().to_string_lossy();
+    let image = env::temp_dir().join(format!("minos-test-{}-{}.img", process::id(), name));
+    let image = image.to_string_lossy().into_owned();
+    let _ = fs::remove_file(&image);
+
+    let out = Output::captured();
+    let mut kernel = Kernel::new(
+        Mode::FCFS,
+        shellmemory::ProgMemory::new(shellmemory::MEM_SIZE),
+        shellmemory::VarMemory::new(shellmemory::VAR_SIZE),
+        shellmemory::FrameTable::new(),
+        tlb::Tlb::new(tlb::TLB_SIZE, tlb::TLB_WAYS, tlb::TlbPolicy::LRU, false),
+        mount(&image, false).expect("Failed to mount the test disk image"),
+        out.clone(),
+    );
+    kernel.init_backing_store().expect("Failed to set up the backing store");
+
+    let input = fs::read_to_string(script)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {e}", script.display()));
+    for line in input.lines() {
+        if let Some(what) = line.strip_prefix("#! damage ") {
+            let what: Vec<&str> = what.split_whitespace().collect();
+            kernel.fs.damage(&what).unwrap_or_else(|e| panic!("Failed to damage the disk: {e}"));
+            continue
+        }
+        interpreter::interpreter(line, &mut kernel);
+        if kernel.exit_requested.is_some() {
+            break
+        }
+        kernel.shell_yield();
+    }
+    if kernel.exit_requested.is_none() {
+        kernel.wait_background();
+    }
+    let _ = fs::remove_file(&image);
+    out.take()
+}
+
+fn scripts() -> Vec<PathBuf> {
+    let mut scripts: Vec<PathBuf> = fs::read_dir(SCRIPTS)
+        .unwrap_or_else(|e| panic!("Failed to list {SCRIPTS}: {e}"))
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            let name = path.to_string_lossy();
+            name.ends_with(".txt") && !name.ends_with(RESULT_SUFFIX)
+        })
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+#[test]
+fn golden_output() {
+    let bless = env::var_os("BLESS").is_some();
+    let mut failures = vec![];
+
+    for script in scripts() {
+        let actual = run_script(&script);
+        let stem = script.file_stem().unwrap_or_default().to_string_lossy();
+        let result = script.with_file_name(format!("{stem}{RESULT_SUFFIX}"));
+        if bless {
+            fs::write(&result, &actual)
+                .unwrap_or_else(|e| panic!("Failed to write {}: {e}", result.display()));
+            continue
+        }
+
+        match fs::read_to_string(&result) {
+            Ok(expected) if expected == actual => {}
+            Ok(expected) => failures.push(format!(
+                "{} doesn't match {}\n{}",
+                script.display(),
+                result.display(),
+                diff(&expected, &actual)
+            )),
+            Err(_) => failures.push(format!(
+                "{} has no expected output, run with BLESS=1 to create {}",
+                script.display(),
+                result.display()
+            )),
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// Lists the lines that differ, good enough to spot what changed without a diff tool
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut res = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        let (e, a) = (expected.get(i), actual.get(i));
+        if e != a {
+            res.push_str(&format!(
+                "line {}:\n  expected: {}\n  actual:   {}\n",
+                i + 1,
+                e.unwrap_or(&"<missing>"),
+                a.unwrap_or(&"<missing>")
+            ));
+        }
+    }
+    res
+}
+
+#[test]
+fn old_disk_image_is_refused() {
+    let image = env::temp_dir().join(format!("minos-test-{}-old.img", process::id()));
+    let mut superblock = vec![0; BLOCK_SIZE];
+    superblock[..8].copy_from_slice(b"MINOSFS1");
+    fs::write(&image, &superblock).expect("Failed to write the old disk image");
+
+    let res = mount(&image.to_string_lossy(), false);
+    let _ = fs::remove_file(&image);
+    match res {
+        Ok(_) => panic!("An image of an older version was mounted"),
+        Err(e) => assert!(e.contains("older version"), "Unexpected error: {e}"),
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+
+pub(crate) const TLB_SIZE: usize = 8;
+pub(crate) const TLB_WAYS: usize = 2;
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum TlbPolicy {
+    FIFO,
+    LRU,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TlbEntry {
+    valid: bool,
+    asid: isize, // Pid of the job the translation belongs to, only checked when entries are tagged
+    page: usize,
+    frame: usize,
+    stamp: usize, // When the entry was filled under FIFO, last used under LRU
+}
+
+impl TlbEntry {
+    fn invalid() -> TlbEntry {
+        TlbEntry{
+            valid: false,
+            asid: 0,
+            page: 0,
+            frame: 0,
+            stamp: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TlbStats {
+    pub(crate) program: String,
+    pub(crate) hits: usize,
+    pub(crate) misses: usize,
+}
+
+/// A set associative translation lookaside buffer caching page to frame translations.
+/// Untagged entries are flushed on every context switch, tagged ones carry the pid of their job.
+pub(crate) struct Tlb {
+    sets: Vec<Vec<TlbEntry>>,
+    pub(crate) policy: TlbPolicy,
+    pub(crate) tagged: bool,
+    clock: usize,
+    current: Option<isize>,
+    pub(crate) flushes: usize,
+    pub(crate) stats: BTreeMap<isize, TlbStats>, // Per pid, finished jobs are kept
+}
+
+impl Tlb {
+    pub(crate) fn new(size: usize, ways: usize, policy: TlbPolicy, tagged: bool) -> Tlb {
+        Tlb{
+            sets: vec![vec![TlbEntry::invalid(); ways]; size / ways],
+            policy,
+            tagged,
+            clock: 0,
+            current: None,
+            flushes: 0,
+            stats: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.sets.len() * self.ways()
+    }
+
+    pub(crate) fn ways(&self) -> usize {
+        self.sets[0].len()
+    }
+
+    fn set_of(&mut self, page: usize) -> &mut Vec<TlbEntry> {
+        let num_sets = self.sets.len();
+        &mut self.sets[page % num_sets]
+    }
+
+    /// Called before a job fetches an instruction, flushes the untagged TLB if the job changed
+    pub(crate) fn switch_to(&mut self, pid: isize, program: &str) {
+        self.stats.entry(pid).or_default().program = String::from(program);
+        if self.current == Some(pid) {
+            return
+        }
+        if !self.tagged && self.current.is_some() {
+            self.flush();
+        }
+        self.current = Some(pid);
+    }
+
+    pub(crate) fn flush(&mut self) {
+        for entry in self.sets.iter_mut().flatten() {
+            entry.valid = false;
+        }
+        self.flushes += 1;
+    }
+
+    /// Looks up the frame of a page for a job, counting the hit or miss
+    pub(crate) fn lookup(&mut self, pid: isize, page: usize) -> Option<usize> {
+        self.clock += 1;
+        let (clock, tagged, policy) = (self.clock, self.tagged, self.policy);
+
+        let hit = self.set_of(page)
+            .iter_mut()
+            .find(|e| e.valid && e.page == page && (!tagged || e.asid == pid));
+        let frame = hit.map(|entry| {
+            if policy == TlbPolicy::LRU {
+                entry.stamp = clock;
+            }
+            entry.frame
+        });
+
+        let stats = self.stats.entry(pid).or_default();
+        if frame.is_some() {
+            stats.hits += 1;
+        } else {
+            stats.misses += 1;
+        }
+        frame
+    }
+
+    /// Caches a translation of a job, replacing an entry of the set if it is full
+    pub(crate) fn insert(&mut self, pid: isize, page: usize, frame: usize) {
+        self.clock += 1;
+        let clock = self.clock;
+
+        let set = self.set_of(page);
+        let way = set.iter()
+            .position(|e| !e.valid)
+            .unwrap_or_else(|| {
+                set.iter()
+                    .enumerate()
+                    .min_by_key(|(_, e)| e.stamp)
+                    .map(|(way, _)| way)
+                    .expect("TLB sets have at least one way")
+            });
+        set[way] = TlbEntry{
+            valid: true,
+            asid: pid,
+            page,
+            frame,
+            stamp: clock,
+        };
+    }
+
+    /// Drops the translations to a frame that no longer holds their page
+    pub(crate) fn invalidate_frame(&mut self, frame: usize) {
+        for entry in self.sets.iter_mut().flatten() {
+            if entry.frame == frame {
+                entry.valid = false;
+            }
+        }
+    }
+}